@@ -0,0 +1,190 @@
+//! AWS `X-Amzn-Trace-Id` propagation.
+//!
+//! See <https://docs.aws.amazon.com/elasticloadbalancing/latest/application/load-balancer-request-tracing.html>.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_web::http::header::HeaderMap;
+
+use crate::hex;
+
+/// Header set by AWS load balancers and X-Ray.
+pub const AMZN_TRACE_ID_HEADER: &str = "x-amzn-trace-id";
+
+/// Trace id of a request, parsed from `X-Amzn-Trace-Id` or generated.
+///
+/// The `Root` is used as the [`RequestID`](crate::RequestID), so it can be
+/// looked up in ALB access logs. `Self`, `Parent`, `Sampled` and custom
+/// fields are kept as received.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AmznTraceId {
+    root: String,
+    parent: Option<String>,
+    sampled: Option<String>,
+    self_id: Option<String>,
+    fields: Vec<(String, String)>,
+    segment_id: String,
+}
+
+impl AmznTraceId {
+    /// Starts a new trace, with a root id in the X-Ray format.
+    pub fn generate() -> AmznTraceId {
+        let epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_secs())
+            .unwrap_or_default();
+
+        AmznTraceId {
+            root: format!("1-{:08x}-{}", epoch, hex::random_id(24)),
+            parent: None,
+            sampled: None,
+            self_id: None,
+            fields: Vec::new(),
+            segment_id: hex::random_id(16),
+        }
+    }
+
+    /// Parses the incoming `X-Amzn-Trace-Id` header.
+    pub fn from_headers(headers: &HeaderMap) -> Option<AmznTraceId> {
+        AmznTraceId::parse(headers.get(AMZN_TRACE_ID_HEADER)?.to_str().ok()?)
+    }
+
+    /// Parses a `X-Amzn-Trace-Id` value, which must have a valid `Root`.
+    pub fn parse(value: &str) -> Option<AmznTraceId> {
+        let mut root = None;
+        let mut parent = None;
+        let mut sampled = None;
+        let mut self_id = None;
+        let mut fields = Vec::new();
+
+        for field in value.split(';').map(str::trim).filter(|f| !f.is_empty()) {
+            let (name, value) = field.split_once('=')?;
+
+            match name {
+                "Root" => root = Some(value.to_owned()),
+                "Parent" => parent = Some(value.to_owned()),
+                "Sampled" => sampled = Some(value.to_owned()),
+                "Self" => self_id = Some(value.to_owned()),
+                _ => fields.push((name.to_owned(), value.to_owned())),
+            }
+        }
+
+        let root = root.filter(|root| is_trace_id(root))?;
+
+        Some(AmznTraceId {
+            root,
+            parent,
+            sampled,
+            self_id,
+            fields,
+            segment_id: hex::random_id(16),
+        })
+    }
+
+    /// Returns the `Root` trace id.
+    pub fn root(&self) -> &str {
+        &self.root
+    }
+
+    /// Returns the `Parent` segment id sent by the caller.
+    pub fn parent(&self) -> Option<&str> {
+        self.parent.as_deref()
+    }
+
+    /// Returns the `Sampled` field, `1`, `0` or `?`.
+    pub fn sampled(&self) -> Option<&str> {
+        self.sampled.as_deref()
+    }
+
+    /// Returns the `Self` field added by the load balancer.
+    pub fn self_id(&self) -> Option<&str> {
+        self.self_id.as_deref()
+    }
+
+    /// Returns the custom fields, in the order they were received.
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// Returns the segment id of this request.
+    pub fn segment_id(&self) -> &str {
+        &self.segment_id
+    }
+
+    /// Returns the `X-Amzn-Trace-Id` to send to downstream services, with
+    /// this request's segment as `Parent`.
+    pub fn header_value(&self) -> String {
+        let mut value = format!("Root={}", self.root);
+
+        if let Some(self_id) = &self.self_id {
+            value.push_str(";Self=");
+            value.push_str(self_id);
+        }
+        value.push_str(";Parent=");
+        value.push_str(&self.segment_id);
+        if let Some(sampled) = &self.sampled {
+            value.push_str(";Sampled=");
+            value.push_str(sampled);
+        }
+        for (name, field) in &self.fields {
+            value.push(';');
+            value.push_str(name);
+            value.push('=');
+            value.push_str(field);
+        }
+
+        value
+    }
+}
+
+/// Checks `value` is an X-Ray trace id: `1-{8 hex epoch}-{24 hex}`.
+fn is_trace_id(value: &str) -> bool {
+    let mut parts = value.split('-');
+
+    parts.next() == Some("1")
+        && parts
+            .next()
+            .is_some_and(|epoch| hex::is_lower_hex(epoch, 8))
+        && parts.next().is_some_and(|id| hex::is_id(id, 24))
+        && parts.next().is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_forwards_alb_header() {
+        let trace = AmznTraceId::parse(
+            "Self=1-67891234-12456789abcdef012345678;Root=1-67891233-abcdef012345678912345678;Parent=53995c3f42cd8ad8;Sampled=1;CalledFrom=app",
+        )
+        .unwrap();
+
+        assert_eq!(trace.root(), "1-67891233-abcdef012345678912345678");
+        assert_eq!(trace.parent(), Some("53995c3f42cd8ad8"));
+        assert_eq!(trace.sampled(), Some("1"));
+        assert_eq!(trace.self_id(), Some("1-67891234-12456789abcdef012345678"));
+        assert_eq!(trace.fields().collect::<Vec<_>>(), [("CalledFrom", "app")]);
+        assert_eq!(
+            trace.header_value(),
+            format!(
+                "Root=1-67891233-abcdef012345678912345678;Self=1-67891234-12456789abcdef012345678;Parent={};Sampled=1;CalledFrom=app",
+                trace.segment_id()
+            )
+        );
+    }
+
+    #[test]
+    fn requires_a_valid_root() {
+        assert!(AmznTraceId::parse("Parent=53995c3f42cd8ad8").is_none());
+        assert!(AmznTraceId::parse("Root=not-a-trace-id").is_none());
+    }
+
+    #[test]
+    fn generated_root_is_valid() {
+        let trace = AmznTraceId::generate();
+
+        assert!(AmznTraceId::parse(&trace.header_value()).is_some());
+    }
+}
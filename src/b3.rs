@@ -0,0 +1,301 @@
+//! Zipkin B3 propagation.
+//!
+//! See <https://github.com/openzipkin/b3-propagation>.
+use actix_web::http::header::{HeaderMap, HeaderName};
+
+use crate::hex;
+
+/// Single header B3 format: `{TraceId}-{SpanId}-{SamplingState}-{ParentSpanId}`.
+pub const B3_HEADER: &str = "b3";
+pub const B3_TRACE_ID_HEADER: &str = "x-b3-traceid";
+pub const B3_SPAN_ID_HEADER: &str = "x-b3-spanid";
+pub const B3_PARENT_SPAN_ID_HEADER: &str = "x-b3-parentspanid";
+pub const B3_SAMPLED_HEADER: &str = "x-b3-sampled";
+pub const B3_FLAGS_HEADER: &str = "x-b3-flags";
+
+/// Which B3 format is written to downstream services. Both formats are
+/// always accepted on incoming requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum B3Format {
+    /// The `b3` header.
+    Single,
+    /// The `X-B3-*` headers.
+    Multi,
+}
+
+/// B3 trace context of a request, read from the incoming headers or
+/// generated.
+///
+/// The trace id is used as the [`RequestID`](crate::RequestID). The span id
+/// identifies this request, the span id sent by the caller becomes the parent
+/// span id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct B3 {
+    trace_id: String,
+    span_id: String,
+    parent_span_id: Option<String>,
+    sampled: Option<bool>,
+    debug: bool,
+}
+
+impl B3 {
+    /// Starts a new sampled trace with a 128 bit trace id.
+    pub fn generate() -> B3 {
+        B3::with_sampling(Some(true), false)
+    }
+
+    fn with_sampling(sampled: Option<bool>, debug: bool) -> B3 {
+        B3 {
+            trace_id: hex::random_id(32),
+            span_id: hex::random_id(16),
+            parent_span_id: None,
+            sampled,
+            debug,
+        }
+    }
+
+    /// Continues the trace of the incoming `b3` header or, when absent, of
+    /// the `X-B3-*` headers.
+    ///
+    /// A sampling only `b3` header (`0`, `1` or `d`), or `X-B3-Sampled` and
+    /// `X-B3-Flags` without ids, starts a new trace with that sampling
+    /// decision.
+    pub fn from_headers(headers: &HeaderMap) -> Option<B3> {
+        match headers.get(B3_HEADER) {
+            Some(value) => B3::parse_single(value.to_str().ok()?),
+            None => B3::parse_multi(headers),
+        }
+    }
+
+    fn parse_single(value: &str) -> Option<B3> {
+        let parts = value.trim().split('-').collect::<Vec<_>>();
+
+        let (trace_id, span_id, sampling, parent_span_id) = match parts.as_slice() {
+            [sampling] => {
+                let (sampled, debug) = parse_sampling_state(sampling)?;
+                return Some(B3::with_sampling(sampled, debug));
+            }
+            [trace_id, span_id] => (*trace_id, *span_id, None, None),
+            [trace_id, span_id, sampling] => (*trace_id, *span_id, Some(*sampling), None),
+            [trace_id, span_id, sampling, parent] => {
+                (*trace_id, *span_id, Some(*sampling), Some(*parent))
+            }
+            _ => return None,
+        };
+
+        let (sampled, debug) = match sampling {
+            Some(sampling) => parse_sampling_state(sampling)?,
+            None => (None, false),
+        };
+
+        B3::continued(trace_id, span_id, parent_span_id, sampled, debug)
+    }
+
+    fn parse_multi(headers: &HeaderMap) -> Option<B3> {
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+        let sampled = match header(B3_SAMPLED_HEADER) {
+            Some("1") | Some("true") => Some(true),
+            Some("0") | Some("false") => Some(false),
+            Some(_) => return None,
+            None => None,
+        };
+        let debug = header(B3_FLAGS_HEADER) == Some("1");
+
+        match (header(B3_TRACE_ID_HEADER), header(B3_SPAN_ID_HEADER)) {
+            (Some(trace_id), Some(span_id)) => B3::continued(
+                trace_id,
+                span_id,
+                header(B3_PARENT_SPAN_ID_HEADER),
+                sampled,
+                debug,
+            ),
+            (None, None) if sampled.is_some() || debug => Some(B3::with_sampling(sampled, debug)),
+            _ => None,
+        }
+    }
+
+    /// Starts a new span within the trace of the caller.
+    fn continued(
+        trace_id: &str,
+        span_id: &str,
+        parent_span_id: Option<&str>,
+        sampled: Option<bool>,
+        debug: bool,
+    ) -> Option<B3> {
+        let valid = (hex::is_id(trace_id, 32) || hex::is_id(trace_id, 16))
+            && hex::is_id(span_id, 16)
+            && parent_span_id.is_none_or(|parent| hex::is_id(parent, 16));
+        if !valid {
+            return None;
+        }
+
+        Some(B3 {
+            trace_id: trace_id.to_owned(),
+            span_id: hex::random_id(16),
+            parent_span_id: Some(span_id.to_owned()),
+            sampled,
+            debug,
+        })
+    }
+
+    /// Returns the trace id, 16 or 32 hex characters.
+    pub fn trace_id(&self) -> &str {
+        &self.trace_id
+    }
+
+    /// Returns the span id of this request.
+    pub fn span_id(&self) -> &str {
+        &self.span_id
+    }
+
+    /// Returns the span id of the caller, if the trace was propagated.
+    pub fn parent_span_id(&self) -> Option<&str> {
+        self.parent_span_id.as_deref()
+    }
+
+    /// Returns the sampling decision, `None` when it is deferred.
+    pub fn sampled(&self) -> Option<bool> {
+        if self.debug {
+            Some(true)
+        } else {
+            self.sampled
+        }
+    }
+
+    /// Returns whether the debug flag is set.
+    pub fn debug(&self) -> bool {
+        self.debug
+    }
+
+    /// Returns the headers to send to downstream services in `format`, with
+    /// this request's span as parent.
+    pub fn headers(&self, format: B3Format) -> Vec<(HeaderName, String)> {
+        match format {
+            B3Format::Single => vec![(HeaderName::from_static(B3_HEADER), self.single_header())],
+            B3Format::Multi => self.multi_headers(),
+        }
+    }
+
+    fn single_header(&self) -> String {
+        let sampling = if self.debug {
+            Some("d")
+        } else {
+            self.sampled.map(|sampled| if sampled { "1" } else { "0" })
+        };
+
+        match sampling {
+            Some(sampling) => format!("{}-{}-{}", self.trace_id, self.span_id, sampling),
+            None => format!("{}-{}", self.trace_id, self.span_id),
+        }
+    }
+
+    fn multi_headers(&self) -> Vec<(HeaderName, String)> {
+        let mut headers = vec![
+            (
+                HeaderName::from_static(B3_TRACE_ID_HEADER),
+                self.trace_id.clone(),
+            ),
+            (
+                HeaderName::from_static(B3_SPAN_ID_HEADER),
+                self.span_id.clone(),
+            ),
+        ];
+
+        if self.debug {
+            headers.push((HeaderName::from_static(B3_FLAGS_HEADER), "1".to_owned()));
+        } else if let Some(sampled) = self.sampled {
+            let sampled = if sampled { "1" } else { "0" };
+            headers.push((
+                HeaderName::from_static(B3_SAMPLED_HEADER),
+                sampled.to_owned(),
+            ));
+        }
+
+        headers
+    }
+}
+
+/// Parses the sampling state of the single header format into the sampled
+/// and debug flags.
+fn parse_sampling_state(value: &str) -> Option<(Option<bool>, bool)> {
+    match value {
+        "1" => Some((Some(true), false)),
+        "0" => Some((Some(false), false)),
+        "d" => Some((Some(true), true)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn parses_single_header() {
+        let req = TestRequest::default()
+            .insert_header((
+                B3_HEADER,
+                "80f198ee56343ba864fe8b2a57d3eff7-e457b5a2e4d86bd1-1-05e3ac9a4f6e3b90",
+            ))
+            .to_http_request();
+        let b3 = B3::from_headers(req.headers()).unwrap();
+
+        assert_eq!(b3.trace_id(), "80f198ee56343ba864fe8b2a57d3eff7");
+        assert_eq!(b3.parent_span_id(), Some("e457b5a2e4d86bd1"));
+        assert_eq!(b3.sampled(), Some(true));
+    }
+
+    #[test]
+    fn parses_multi_headers() {
+        let req = TestRequest::default()
+            .insert_header((B3_TRACE_ID_HEADER, "463ac35c9f6413ad"))
+            .insert_header((B3_SPAN_ID_HEADER, "a2fb4a1d1a96d312"))
+            .insert_header((B3_SAMPLED_HEADER, "0"))
+            .to_http_request();
+        let b3 = B3::from_headers(req.headers()).unwrap();
+
+        assert_eq!(b3.trace_id(), "463ac35c9f6413ad");
+        assert_eq!(b3.parent_span_id(), Some("a2fb4a1d1a96d312"));
+        assert_eq!(b3.sampled(), Some(false));
+    }
+
+    #[test]
+    fn sampling_only_header_starts_a_new_trace() {
+        let req = TestRequest::default()
+            .insert_header((B3_HEADER, "d"))
+            .to_http_request();
+        let b3 = B3::from_headers(req.headers()).unwrap();
+
+        assert_eq!(b3.parent_span_id(), None);
+        assert!(b3.debug());
+    }
+
+    #[test]
+    fn sampling_only_multi_headers_start_a_new_trace() {
+        let req = TestRequest::default()
+            .insert_header((B3_SAMPLED_HEADER, "0"))
+            .to_http_request();
+        let b3 = B3::from_headers(req.headers()).unwrap();
+
+        assert_eq!(b3.parent_span_id(), None);
+        assert_eq!(b3.sampled(), Some(false));
+        assert_eq!(b3.headers(B3Format::Multi)[2].1, "0");
+
+        let req = TestRequest::default().to_http_request();
+        assert_eq!(B3::from_headers(req.headers()), None);
+    }
+
+    #[test]
+    fn writes_both_formats() {
+        let b3 = B3::generate();
+
+        let single = b3.headers(B3Format::Single);
+        assert_eq!(single[0].1, format!("{}-{}-1", b3.trace_id(), b3.span_id()));
+
+        let multi = b3.headers(B3Format::Multi);
+        assert_eq!(multi.len(), 3);
+        assert_eq!(multi[0].1, b3.trace_id());
+    }
+}
@@ -0,0 +1,193 @@
+//! Task-local access to the id of the request being handled.
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use actix_web::error::BlockingError;
+use actix_web::http::header::{HeaderName, HeaderValue};
+use pin_project_lite::pin_project;
+use tokio::task::JoinHandle;
+
+use crate::{Config, RequestID};
+
+/// The current request id, with the configuration of the middleware that
+/// resolved it.
+#[derive(Clone)]
+struct Current {
+    id: RequestID,
+    config: Option<Arc<Config>>,
+}
+
+tokio::task_local! {
+    static CURRENT: Current;
+}
+
+impl RequestID {
+    /// Returns the id of the request the current task is handling.
+    ///
+    /// The id is set by [`RequestIDMiddleware`](crate::RequestIDMiddleware)
+    /// while the inner service runs, so code without access to the
+    /// `HttpRequest` (repositories, clients...) can still log it. Returns
+    /// `None` outside of a request.
+    ///
+    /// ```
+    /// use actix_web_requestid::RequestID;
+    ///
+    /// fn load_user(name: &str) {
+    ///     if let Some(id) = RequestID::current() {
+    ///         println!("[{}] loading {}", id, name);
+    ///     }
+    /// }
+    /// ```
+    pub fn current() -> Option<RequestID> {
+        CURRENT.try_with(|current| current.id.clone()).ok()
+    }
+}
+
+/// Calls `call` with `id` as the current request id.
+pub(crate) fn sync_scope<F, R>(id: RequestID, config: Arc<Config>, call: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let current = Current {
+        id,
+        config: Some(config),
+    };
+
+    CURRENT.sync_scope(current, call)
+}
+
+/// Polls `fut` with `id` as the current request id, if any.
+pub(crate) fn scope<F: Future>(
+    id: Option<RequestID>,
+    config: &Arc<Config>,
+    fut: F,
+) -> WithRequestID<F> {
+    WithRequestID {
+        current: id.map(|id| Current {
+            id,
+            config: Some(Arc::clone(config)),
+        }),
+        inner: fut,
+    }
+}
+
+/// Headers propagating the current request id to downstream services, in
+/// the header and format configured on the middleware.
+#[cfg_attr(not(any(feature = "awc", feature = "reqwest")), allow(dead_code))]
+pub(crate) fn outgoing_headers() -> Vec<(HeaderName, HeaderValue)> {
+    CURRENT
+        .try_with(|current| match &current.config {
+            Some(config) => config.outgoing_headers(&current.id),
+            None => Config::default().outgoing_headers(&current.id),
+        })
+        .unwrap_or_default()
+}
+
+pin_project! {
+    /// Future polled with a request id as the current one, see
+    /// [`RequestIDFutureExt`].
+    #[must_use = "futures do nothing unless polled"]
+    pub struct WithRequestID<F> {
+        current: Option<Current>,
+        #[pin]
+        inner: F,
+    }
+}
+
+impl<F: Future> Future for WithRequestID<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let this = self.project();
+        let inner = this.inner;
+
+        match this.current {
+            Some(current) => CURRENT.sync_scope(current.clone(), || inner.poll(cx)),
+            None => inner.poll(cx),
+        }
+    }
+}
+
+/// Carries the current request id over to futures running outside of the
+/// request, like spawned tasks.
+pub trait RequestIDFutureExt: Future + Sized {
+    /// Polls this future with `id` as the current request id.
+    fn with_request_id(self, id: RequestID) -> WithRequestID<Self> {
+        WithRequestID {
+            current: Some(Current { id, config: None }),
+            inner: self,
+        }
+    }
+
+    /// Polls this future with the request id current at the time of the
+    /// call, if any.
+    fn in_current_request(self) -> WithRequestID<Self> {
+        WithRequestID {
+            current: CURRENT.try_with(Current::clone).ok(),
+            inner: self,
+        }
+    }
+}
+
+impl<F: Future> RequestIDFutureExt for F {}
+
+/// Spawns a future on the current actix runtime, keeping the current
+/// request id.
+///
+/// ```
+/// # async fn index() {
+/// actix_web_requestid::spawn(async {
+///     // Still the id of the request that spawned the task.
+///     let id = actix_web_requestid::RequestID::current();
+/// });
+/// # }
+/// ```
+pub fn spawn<F>(fut: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    actix_web::rt::spawn(fut.in_current_request())
+}
+
+/// Runs a blocking closure on the thread pool, like [`web::block`], keeping
+/// the current request id.
+///
+/// [`web::block`]: actix_web::web::block
+pub fn block<F, R>(f: F) -> impl Future<Output = Result<R, BlockingError>>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let current = CURRENT.try_with(Current::clone).ok();
+
+    actix_web::web::block(move || match current {
+        Some(current) => CURRENT.sync_scope(current, f),
+        None => f(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RequestIDSource;
+
+    #[actix_rt::test]
+    async fn spawned_work_keeps_the_request_id() {
+        let id = RequestID::new("spawned".to_owned(), RequestIDSource::Generated);
+
+        let (spawned, blocked) = async {
+            let spawned = spawn(async { RequestID::current() }).await.unwrap();
+            let blocked = block(RequestID::current).await.unwrap();
+            (spawned, blocked)
+        }
+        .with_request_id(id.clone())
+        .await;
+
+        assert_eq!(spawned, Some(id.clone()));
+        assert_eq!(blocked, Some(id));
+        assert_eq!(spawn(async { RequestID::current() }).await.unwrap(), None);
+    }
+}
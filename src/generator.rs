@@ -0,0 +1,163 @@
+//! Request id generators.
+#[cfg(feature = "ulid")]
+use std::sync::Mutex;
+
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+
+/// Generates new request ids.
+///
+/// Implement this trait to plug your own id scheme into
+/// [`RequestIDMiddleware`](crate::RequestIDMiddleware). Any
+/// `Fn() -> String` closure is also a generator.
+///
+/// ```
+/// use actix_web::App;
+/// use actix_web_requestid::RequestIDMiddleware;
+///
+/// let app = App::new()
+///     .wrap(RequestIDMiddleware::default().generator(|| "my-own-id".to_owned()));
+/// ```
+pub trait RequestIDGenerator: Send + Sync {
+    /// Returns a new request id.
+    fn generate(&self) -> String;
+}
+
+impl<F> RequestIDGenerator for F
+where
+    F: Fn() -> String + Send + Sync,
+{
+    fn generate(&self) -> String {
+        self()
+    }
+}
+
+/// Generates random alphanumeric ids. This is the default generator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlphanumericGenerator {
+    len: usize,
+}
+
+impl AlphanumericGenerator {
+    /// Creates a generator producing ids of `len` characters.
+    pub fn new(len: usize) -> Self {
+        AlphanumericGenerator { len }
+    }
+}
+
+impl Default for AlphanumericGenerator {
+    fn default() -> Self {
+        AlphanumericGenerator::new(10)
+    }
+}
+
+impl RequestIDGenerator for AlphanumericGenerator {
+    fn generate(&self) -> String {
+        rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .map(char::from)
+            .take(self.len)
+            .collect()
+    }
+}
+
+/// Generates random UUID v4 ids.
+#[cfg(feature = "uuid-v4")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct UuidV4Generator;
+
+#[cfg(feature = "uuid-v4")]
+impl RequestIDGenerator for UuidV4Generator {
+    fn generate(&self) -> String {
+        uuid::Uuid::new_v4().to_string()
+    }
+}
+
+/// Generates time-ordered UUID v7 ids.
+///
+/// Ids generated later sort after earlier ones, which keeps database indexes
+/// on request id columns compact.
+#[cfg(feature = "uuid-v7")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct UuidV7Generator;
+
+#[cfg(feature = "uuid-v7")]
+impl RequestIDGenerator for UuidV7Generator {
+    fn generate(&self) -> String {
+        uuid::Uuid::now_v7().to_string()
+    }
+}
+
+/// Generates lexicographically sortable ULID ids.
+///
+/// Ids are monotonic: when several ids are generated within the same
+/// millisecond, the random part is incremented so they still sort in
+/// generation order.
+#[cfg(feature = "ulid")]
+#[derive(Default)]
+pub struct UlidGenerator {
+    inner: Mutex<ulid::Generator>,
+}
+
+#[cfg(feature = "ulid")]
+impl std::fmt::Debug for UlidGenerator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UlidGenerator").finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "ulid")]
+impl RequestIDGenerator for UlidGenerator {
+    fn generate(&self) -> String {
+        let mut generator = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+
+        // Overflow only happens after 2^80 ids in the same millisecond.
+        generator
+            .generate()
+            .unwrap_or_else(|_| ulid::Ulid::new())
+            .to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alphanumeric_generator_respects_length() {
+        let id = AlphanumericGenerator::new(16).generate();
+
+        assert_eq!(id.len(), 16);
+        assert!(id.chars().all(|c| c.is_ascii_alphanumeric()));
+    }
+
+    #[cfg(feature = "uuid-v4")]
+    #[test]
+    fn uuid_v4_generator_generates_v4_uuids() {
+        let id = uuid::Uuid::parse_str(&UuidV4Generator.generate()).unwrap();
+
+        assert_eq!(id.get_version_num(), 4);
+    }
+
+    #[cfg(feature = "uuid-v7")]
+    #[test]
+    fn uuid_v7_generator_generates_ordered_uuids() {
+        let first = uuid::Uuid::parse_str(&UuidV7Generator.generate()).unwrap();
+        let second = uuid::Uuid::parse_str(&UuidV7Generator.generate()).unwrap();
+
+        assert_eq!(first.get_version_num(), 7);
+        assert!(first <= second);
+    }
+
+    #[cfg(feature = "ulid")]
+    #[test]
+    fn ulid_generator_is_monotonic() {
+        let generator = UlidGenerator::default();
+        let ids = (0..100).map(|_| generator.generate()).collect::<Vec<_>>();
+
+        let mut sorted = ids.clone();
+        sorted.sort();
+        assert_eq!(ids, sorted);
+        assert!(ids.windows(2).all(|w| w[0] != w[1]));
+    }
+}
@@ -0,0 +1,33 @@
+//! Hex encoded ids used by the tracing propagation formats.
+use rand::Rng;
+
+/// Checks `value` is `len` lowercase hex characters.
+pub(crate) fn is_lower_hex(value: &str, len: usize) -> bool {
+    value.len() == len
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+/// Checks `value` is only made of zeros, which is an invalid id.
+pub(crate) fn is_zero(value: &str) -> bool {
+    value.bytes().all(|b| b == b'0')
+}
+
+/// Checks `value` is a valid id of `len` lowercase hex characters.
+pub(crate) fn is_id(value: &str, len: usize) -> bool {
+    is_lower_hex(value, len) && !is_zero(value)
+}
+
+/// Generates a random id of `len` lowercase hex characters.
+pub(crate) fn random_id(len: usize) -> String {
+    let mut rng = rand::thread_rng();
+    loop {
+        let id = (0..len)
+            .map(|_| char::from_digit(rng.gen_range(0..16), 16).unwrap())
+            .collect::<String>();
+        if !is_zero(&id) {
+            return id;
+        }
+    }
+}
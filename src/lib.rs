@@ -7,24 +7,210 @@
 //! `request-id` http header. To access requestID data, [`RequestID`] actix
 //!  extractor must be used.
 //!
+//! Code that has no access to the request, like repositories or clients,
+//! can get the id with [`RequestID::current`] while the middleware handles
+//! the request. Use [`spawn`], [`block`] or [`RequestIDFutureExt`] to keep
+//! it in background work started by a handler.
+//!
+//! With the `awc` feature, [`ClientRequestExt::with_request_id`] forwards
+//! the current id to other services called with `awc`. With the `reqwest`
+//! feature, the [`RequestIDPropagation`] middleware does the same for
+//! `reqwest-middleware` clients.
+//!
+//! [`LoggerExt::log_request_id`] adds the id to the access logs of
+//! `actix_web::middleware::Logger`.
+//!
+//! With the `problem-json` feature, [`ProblemJson`] renders error responses
+//! as `application/problem+json` documents embedding the id, for users to
+//! quote in support requests.
+//!
 //! It is still useable without the middleware. The first time you try to
 //! extract the id, it will be generated. Then reused along the request.
 //! You can for exemple use that in a Logging or tracing middleware.
+//!
+//! When a request already carries a valid `request-id` header (for
+//! example set by a load balancer or an upstream service), the middleware
+//! adopts it instead of generating a new one. What makes an incoming id valid
+//! is configured with [`Validation`]. [`RequestID::source`] tells
+//! whether an id was propagated or generated.
+//!
+//! With the `tracing` feature, the middleware runs the rest of the request
+//! within a `request` span recording the `request_id`, `method` and `path`,
+//! so events logged by handlers are correlated with the id.
+//!
+//! With the `opentelemetry` feature, the middleware extracts the remote
+//! context with the global propagator and runs the rest of the request within
+//! a server span recording the `request_id`. The context is available with
+//! the [`OtelContext`] extractor.
+use std::cell::Cell;
 use std::convert::Infallible;
 use std::future::{ready, Future, Ready};
 use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
 
+use actix_web::body::{EitherBody, MessageBody};
 use actix_web::dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform};
-use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::error::ErrorBadRequest;
+use actix_web::guard::Guard;
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+#[cfg(feature = "tracing")]
+use actix_web::http::Method;
 use actix_web::{Error, FromRequest, HttpMessage, HttpRequest};
-use rand::distributions::Alphanumeric;
-use rand::Rng;
+
+mod amzn_trace_id;
+mod b3;
+mod current;
+mod generator;
+mod hex;
+mod logger;
+#[cfg(feature = "opentelemetry")]
+mod otel;
+mod outgoing;
+#[cfg(feature = "problem-json")]
+mod problem;
+mod trace_context;
+mod trust;
+mod validation;
+
+pub use amzn_trace_id::{AmznTraceId, AMZN_TRACE_ID_HEADER};
+pub use b3::{
+    B3Format, B3, B3_FLAGS_HEADER, B3_HEADER, B3_PARENT_SPAN_ID_HEADER, B3_SAMPLED_HEADER,
+    B3_SPAN_ID_HEADER, B3_TRACE_ID_HEADER,
+};
+pub use current::{block, spawn, RequestIDFutureExt, WithRequestID};
+#[cfg(feature = "ulid")]
+pub use generator::UlidGenerator;
+#[cfg(feature = "uuid-v4")]
+pub use generator::UuidV4Generator;
+#[cfg(feature = "uuid-v7")]
+pub use generator::UuidV7Generator;
+pub use generator::{AlphanumericGenerator, RequestIDGenerator};
+pub use ipnet::IpNet;
+pub use logger::{LoggerExt, LOGGER_REQUEST_ID};
+#[cfg(feature = "opentelemetry")]
+pub use otel::OtelContext;
+#[cfg(feature = "awc")]
+pub use outgoing::ClientRequestExt;
+#[cfg(feature = "reqwest")]
+pub use outgoing::RequestIDPropagation;
+#[cfg(feature = "problem-json")]
+pub use problem::{ProblemJson, ProblemJsonService, PROBLEM_JSON};
+pub use trace_context::{TraceContext, TRACEPARENT_HEADER, TRACESTATE_HEADER};
+pub use trust::TrustedPeers;
+use validation::Validated;
+pub use validation::{OnInvalid, Validation};
 
 pub const REQUEST_ID_HEADER: &str = "request-id";
 
+/// Maximum length of an incoming request id that will be accepted.
+pub const MAX_INCOMING_REQUEST_ID_LEN: usize = 128;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct RequestID {
     inner: String,
+    source: RequestIDSource,
+    trace_context: Option<TraceContext>,
+    b3: Option<B3>,
+    amzn_trace_id: Option<AmznTraceId>,
+}
+
+/// Where a [`RequestID`] comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestIDSource {
+    /// The id was generated for this request.
+    Generated,
+    /// The id was taken from the incoming request headers.
+    Incoming,
+}
+
+impl RequestID {
+    fn new(inner: String, source: RequestIDSource) -> Self {
+        RequestID {
+            inner,
+            source,
+            trace_context: None,
+            b3: None,
+            amzn_trace_id: None,
+        }
+    }
+
+    fn from_trace_context(trace_context: TraceContext, source: RequestIDSource) -> Self {
+        RequestID {
+            inner: trace_context.trace_id().to_owned(),
+            source,
+            trace_context: Some(trace_context),
+            b3: None,
+            amzn_trace_id: None,
+        }
+    }
+
+    fn from_b3(b3: B3, source: RequestIDSource) -> Self {
+        RequestID {
+            inner: b3.trace_id().to_owned(),
+            source,
+            trace_context: None,
+            b3: Some(b3),
+            amzn_trace_id: None,
+        }
+    }
+
+    fn from_amzn_trace_id(amzn_trace_id: AmznTraceId, source: RequestIDSource) -> Self {
+        RequestID {
+            inner: amzn_trace_id.root().to_owned(),
+            source,
+            trace_context: None,
+            b3: None,
+            amzn_trace_id: Some(amzn_trace_id),
+        }
+    }
+
+    /// Returns the id as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.inner
+    }
+
+    /// Returns whether the id was generated or propagated from the request.
+    pub fn source(&self) -> RequestIDSource {
+        self.source
+    }
+
+    /// Returns the id as a [`Uuid`](uuid::Uuid), if it is one.
+    ///
+    /// This is always the case for ids produced by [`UuidV4Generator`] or
+    /// [`UuidV7Generator`].
+    #[cfg(any(feature = "uuid-v4", feature = "uuid-v7"))]
+    pub fn uuid(&self) -> Option<uuid::Uuid> {
+        uuid::Uuid::parse_str(&self.inner).ok()
+    }
+
+    /// Returns the time embedded in the id, if it is a ULID.
+    ///
+    /// This is always the case for ids produced by [`UlidGenerator`].
+    #[cfg(feature = "ulid")]
+    pub fn ulid_timestamp(&self) -> Option<std::time::SystemTime> {
+        ulid::Ulid::from_string(&self.inner)
+            .ok()
+            .map(|ulid| ulid.datetime())
+    }
+
+    /// Returns the W3C trace context the id was taken from, when the
+    /// middleware uses [`Propagation::TraceContext`].
+    pub fn trace_context(&self) -> Option<&TraceContext> {
+        self.trace_context.as_ref()
+    }
+
+    /// Returns the B3 trace context the id was taken from, when the
+    /// middleware uses [`Propagation::B3`].
+    pub fn b3(&self) -> Option<&B3> {
+        self.b3.as_ref()
+    }
+
+    /// Returns the AWS trace id the id was taken from, when the middleware
+    /// uses [`Propagation::AmznTraceId`].
+    pub fn amzn_trace_id(&self) -> Option<&AmznTraceId> {
+        self.amzn_trace_id.as_ref()
+    }
 }
 
 impl From<RequestID> for String {
@@ -61,22 +247,471 @@ impl FromRequest for RequestID {
 
 /// Request id middleware
 ///
+/// The middleware is configured by chaining its builder methods. The
+/// configuration is frozen once the middleware is passed to `wrap`, and shared
+/// by the services of all workers without being copied.
+///
 /// ```
 /// use actix_web::*;
-/// use actix_web_requestid::{RequestIDMiddleware};
+/// use actix_web::http::header::HeaderName;
+/// use actix_web_requestid::{EchoHeaders, RequestIDMiddleware};
 ///
 /// let app = App::new()
-///     .wrap(RequestIDMiddleware::default());
+///     .wrap(RequestIDMiddleware::new());
+///
+/// let app = App::new().wrap(
+///     RequestIDMiddleware::new()
+///         .header(HeaderName::from_static("x-request-id"))
+///         .alias_header(HeaderName::from_static("x-correlation-id"))
+///         .echo(EchoHeaders::All),
+/// );
 /// ```
-#[derive(Default)]
-pub struct RequestIDMiddleware {}
+///
+/// The middleware can also wrap scopes and resources, to apply a different
+/// configuration to some routes. When several middleware handle the same
+/// request, the innermost one wins: its configuration resolves, validates and
+/// echoes the id, and the outer ones step aside. Nested middleware are
+/// detected while the request is routed, so a middleware calling the rest of
+/// the app asynchronously in between hides them. A rejection happens before
+/// routing though, so [`OnInvalid::Reject`] belongs on the innermost one.
+///
+/// ```
+/// use actix_web::*;
+/// use actix_web_requestid::{RequestIDMiddleware, TrustedPeers};
+///
+/// let app = App::new()
+///     // Internal routes adopt any incoming id.
+///     .wrap(RequestIDMiddleware::new())
+///     .service(
+///         // Public routes never do.
+///         web::scope("/public")
+///             .wrap(RequestIDMiddleware::new().trusted_peers(TrustedPeers::new([]))),
+///     );
+/// ```
+#[derive(Clone, Default)]
+pub struct RequestIDMiddleware {
+    config: Arc<Config>,
+}
+
+/// Format used to read the incoming id and generate new ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Propagation {
+    /// A plain request id header. This is the default.
+    RequestID,
+    /// W3C Trace Context: the id is the trace id of `traceparent`, a new
+    /// trace is started when it is absent or invalid. The configured
+    /// generator and [`Validation`] are not used.
+    TraceContext,
+    /// Zipkin B3: the id is the B3 trace id, read from either the single
+    /// `b3` header or the `X-B3-*` headers. The format is the one written to
+    /// downstream services. The configured generator and [`Validation`] are
+    /// not used.
+    B3(B3Format),
+    /// AWS `X-Amzn-Trace-Id`: the id is the `Root` of the header, as set by
+    /// load balancers. The configured generator and [`Validation`] are not
+    /// used.
+    AmznTraceId,
+}
+
+/// Which headers the request id is written to on the response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EchoHeaders {
+    /// Do not add the id to the response.
+    None,
+    /// Only the primary header. This is the default.
+    Primary,
+    /// The primary header and every alias header.
+    All,
+}
+
+/// What to do when the response already has a request id header, for
+/// example set by a handler or an inner middleware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderConflict {
+    /// Replace the existing value. This is the default.
+    Overwrite,
+    /// Keep the existing value.
+    KeepExisting,
+    /// Add the id as another value of the header.
+    Append,
+}
+
+/// What to do when a request id cannot be encoded as a header value.
+///
+/// This can happen with custom generators producing control characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidHeaderPolicy {
+    /// Strip the characters that are not visible ASCII. This is the default.
+    Sanitize,
+    /// Replace the id with one from [`AlphanumericGenerator`].
+    Regenerate,
+    /// Keep the id but do not add it to the response, logging a warning.
+    Skip,
+}
+
+/// Predicate on requests bypassing the middleware.
+type Exclusion = Arc<dyn Fn(&ServiceRequest) -> bool + Send + Sync>;
+
+#[derive(Clone)]
+struct Config {
+    generator: Arc<dyn RequestIDGenerator>,
+    header: HeaderName,
+    aliases: Vec<HeaderName>,
+    echo: EchoHeaders,
+    invalid_header: InvalidHeaderPolicy,
+    header_conflict: HeaderConflict,
+    validation: Validation,
+    trusted_peers: Option<TrustedPeers>,
+    propagation: Propagation,
+    exclusions: Vec<Exclusion>,
+    inject_header: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            generator: Arc::new(AlphanumericGenerator::default()),
+            header: HeaderName::from_static(REQUEST_ID_HEADER),
+            aliases: Vec::new(),
+            echo: EchoHeaders::Primary,
+            invalid_header: InvalidHeaderPolicy::Sanitize,
+            header_conflict: HeaderConflict::Overwrite,
+            validation: Validation::default(),
+            trusted_peers: None,
+            propagation: Propagation::RequestID,
+            exclusions: Vec::new(),
+            inject_header: false,
+        }
+    }
+}
+
+impl Config {
+    /// Whether the request bypasses the middleware.
+    fn is_excluded(&self, req: &ServiceRequest) -> bool {
+        self.exclusions.iter().any(|excluded| excluded(req))
+    }
+
+    /// Headers an incoming id is read from, in order of preference.
+    fn incoming_headers(&self) -> impl Iterator<Item = &HeaderName> {
+        std::iter::once(&self.header).chain(&self.aliases)
+    }
+
+    /// Generates a new id in the configured format.
+    fn generate(&self) -> RequestID {
+        match self.propagation {
+            Propagation::RequestID => {
+                RequestID::new(self.generator.generate(), RequestIDSource::Generated)
+            }
+            Propagation::TraceContext => {
+                RequestID::from_trace_context(TraceContext::generate(), RequestIDSource::Generated)
+            }
+            Propagation::B3(_) => RequestID::from_b3(B3::generate(), RequestIDSource::Generated),
+            Propagation::AmznTraceId => {
+                RequestID::from_amzn_trace_id(AmznTraceId::generate(), RequestIDSource::Generated)
+            }
+        }
+    }
+
+    /// Reads the id sent with the request, if any and trusted.
+    ///
+    /// Fails when the id is invalid and [`OnInvalid::Reject`] is configured.
+    fn incoming_id(&self, req: &ServiceRequest) -> Result<Option<RequestID>, Error> {
+        if let Some(trusted_peers) = &self.trusted_peers {
+            if !trusted_peers.is_trusted(req) {
+                return Ok(None);
+            }
+        }
+
+        match self.propagation {
+            Propagation::RequestID => {
+                let value = match self
+                    .incoming_headers()
+                    .find_map(|header| req.headers().get(header))
+                {
+                    Some(value) => value,
+                    None => return Ok(None),
+                };
+
+                match self.validation.validate(value) {
+                    Validated::Accept(id) => {
+                        Ok(Some(RequestID::new(id, RequestIDSource::Incoming)))
+                    }
+                    Validated::Replace => Ok(None),
+                    Validated::Reject => Err(ErrorBadRequest("invalid request id")),
+                }
+            }
+            Propagation::TraceContext => Ok(TraceContext::from_headers(req.headers())
+                .map(|context| RequestID::from_trace_context(context, RequestIDSource::Incoming))),
+            Propagation::B3(_) => Ok(B3::from_headers(req.headers()).map(|b3| {
+                let source = match b3.parent_span_id() {
+                    Some(_) => RequestIDSource::Incoming,
+                    None => RequestIDSource::Generated,
+                };
+                RequestID::from_b3(b3, source)
+            })),
+            Propagation::AmznTraceId => Ok(AmznTraceId::from_headers(req.headers())
+                .map(|trace| RequestID::from_amzn_trace_id(trace, RequestIDSource::Incoming))),
+        }
+    }
+
+    /// Headers propagating `id` to downstream services: the primary header,
+    /// and the headers of the trace format the id was taken from.
+    fn outgoing_headers(&self, id: &RequestID) -> Vec<(HeaderName, HeaderValue)> {
+        let mut headers = vec![(self.header.clone(), id.to_string())];
+
+        if let Some(context) = id.trace_context() {
+            headers.push((
+                HeaderName::from_static(TRACEPARENT_HEADER),
+                context.traceparent(),
+            ));
+            if let Some(trace_state) = context.trace_state() {
+                headers.push((
+                    HeaderName::from_static(TRACESTATE_HEADER),
+                    trace_state.to_owned(),
+                ));
+            }
+        }
+
+        if let Some(b3) = id.b3() {
+            let format = match self.propagation {
+                Propagation::B3(format) => format,
+                _ => B3Format::Single,
+            };
+            headers.extend(b3.headers(format));
+        }
+
+        if let Some(amzn_trace_id) = id.amzn_trace_id() {
+            headers.push((
+                HeaderName::from_static(AMZN_TRACE_ID_HEADER),
+                amzn_trace_id.header_value(),
+            ));
+        }
+
+        headers
+            .into_iter()
+            .filter_map(|(name, value)| Some((name, HeaderValue::from_str(&value).ok()?)))
+            .collect()
+    }
+
+    /// Headers the id is echoed on in the response.
+    fn echo_headers(&self, value: Option<HeaderValue>) -> EchoedHeaders {
+        let names = match self.echo {
+            EchoHeaders::None => Vec::new(),
+            EchoHeaders::Primary => vec![self.header.clone()],
+            EchoHeaders::All => self.incoming_headers().cloned().collect(),
+        };
+
+        let headers = match value {
+            Some(value) => names
+                .into_iter()
+                .map(|name| (name, value.clone()))
+                .collect(),
+            None => Vec::new(),
+        };
+
+        EchoedHeaders {
+            headers,
+            conflict: self.header_conflict,
+        }
+    }
+
+    /// Returns the request id as a header value, applying the
+    /// [`InvalidHeaderPolicy`] if it cannot be encoded.
+    ///
+    /// A replaced id is stored back in the request so the extractor and the
+    /// response header agree.
+    fn header_value(&self, req: &ServiceRequest) -> Option<HeaderValue> {
+        let id = req.request_id();
+        if let Ok(value) = HeaderValue::from_str(id.as_str()) {
+            return Some(value);
+        }
+
+        let replacement = match self.invalid_header {
+            InvalidHeaderPolicy::Skip => {
+                log::warn!(
+                    "request id {:?} is not a valid header value, not adding it to the response",
+                    id.as_str()
+                );
+                return None;
+            }
+            InvalidHeaderPolicy::Sanitize => {
+                let sanitized = id
+                    .as_str()
+                    .chars()
+                    .filter(char::is_ascii_graphic)
+                    .collect::<String>();
+
+                if sanitized.is_empty() {
+                    RequestID::new(
+                        AlphanumericGenerator::default().generate(),
+                        RequestIDSource::Generated,
+                    )
+                } else {
+                    RequestID {
+                        inner: sanitized,
+                        ..id
+                    }
+                }
+            }
+            InvalidHeaderPolicy::Regenerate => RequestID::new(
+                AlphanumericGenerator::default().generate(),
+                RequestIDSource::Generated,
+            ),
+        };
+
+        let value = HeaderValue::from_str(replacement.as_str()).ok();
+        req.extensions_mut().insert(replacement);
+        value
+    }
+}
+
+impl RequestIDMiddleware {
+    /// Creates a middleware with the default configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn config_mut(&mut self) -> &mut Config {
+        Arc::make_mut(&mut self.config)
+    }
+
+    /// Sets the generator used for requests without a usable incoming id.
+    ///
+    /// The same generator is used by the [`RequestID`] extractor when the id
+    /// is lazily created during the request.
+    pub fn generator<G>(mut self, generator: G) -> Self
+    where
+        G: RequestIDGenerator + 'static,
+    {
+        self.config_mut().generator = Arc::new(generator);
+        self
+    }
+
+    /// Sets the primary header the id is read from and echoed on.
+    ///
+    /// Defaults to [`REQUEST_ID_HEADER`].
+    pub fn header(mut self, header: HeaderName) -> Self {
+        self.config_mut().header = header;
+        self
+    }
+
+    /// Adds an alias header to read incoming ids from.
+    ///
+    /// Aliases are checked in the order they were added, after the primary
+    /// header.
+    pub fn alias_header(mut self, header: HeaderName) -> Self {
+        self.config_mut().aliases.push(header);
+        self
+    }
+
+    /// Sets which headers the id is echoed on in the response.
+    pub fn echo(mut self, echo: EchoHeaders) -> Self {
+        self.config_mut().echo = echo;
+        self
+    }
+
+    /// Sets what to do when the response already has a request id header.
+    pub fn header_conflict(mut self, conflict: HeaderConflict) -> Self {
+        self.config_mut().header_conflict = conflict;
+        self
+    }
+
+    /// Sets the rules incoming ids must follow to be adopted.
+    pub fn validation(mut self, validation: Validation) -> Self {
+        self.config_mut().validation = validation;
+        self
+    }
+
+    /// Sets the format used to read incoming ids and generate new ones.
+    pub fn propagation(mut self, propagation: Propagation) -> Self {
+        self.config_mut().propagation = propagation;
+        self
+    }
+
+    /// Only adopts incoming ids sent by trusted peers.
+    ///
+    /// By default ids are adopted whatever the peer.
+    pub fn trusted_peers(mut self, trusted_peers: TrustedPeers) -> Self {
+        self.config_mut().trusted_peers = Some(trusted_peers);
+        self
+    }
+
+    /// Sets what to do when the id cannot be encoded as a header value.
+    pub fn invalid_header(mut self, policy: InvalidHeaderPolicy) -> Self {
+        self.config_mut().invalid_header = policy;
+        self
+    }
+
+    /// Writes the resolved id in the primary header of the incoming request,
+    /// replacing any value it had.
+    ///
+    /// Middleware and handlers reading request headers, rather than the
+    /// [`RequestID`] extractor, then see the same id. Disabled by default.
+    pub fn inject_header(mut self, inject: bool) -> Self {
+        self.config_mut().inject_header = inject;
+        self
+    }
+
+    /// Bypasses the middleware for requests to exactly `path`.
+    ///
+    /// Excluded requests get no id generated nor echoed, unless a handler
+    /// extracts the [`RequestID`], which is then lazily generated.
+    ///
+    /// ```
+    /// use actix_web_requestid::RequestIDMiddleware;
+    ///
+    /// let middleware = RequestIDMiddleware::new()
+    ///     .exclude("/healthz")
+    ///     .exclude_prefix("/metrics");
+    /// ```
+    pub fn exclude(self, path: impl Into<String>) -> Self {
+        let path = path.into();
+        self.exclude_with(move |req| req.path() == path)
+    }
+
+    /// Bypasses the middleware for requests whose path starts with `prefix`.
+    pub fn exclude_prefix(self, prefix: impl Into<String>) -> Self {
+        let prefix = prefix.into();
+        self.exclude_with(move |req| req.path().starts_with(&prefix))
+    }
+
+    /// Bypasses the middleware for requests whose path matches `regex`.
+    #[cfg(feature = "regex")]
+    pub fn exclude_regex(self, regex: regex::Regex) -> Self {
+        self.exclude_with(move |req| regex.is_match(req.path()))
+    }
+
+    /// Bypasses the middleware for requests matching `guard`.
+    ///
+    /// ```
+    /// use actix_web::guard;
+    /// use actix_web_requestid::RequestIDMiddleware;
+    ///
+    /// let middleware = RequestIDMiddleware::new().exclude_guard(guard::Options());
+    /// ```
+    pub fn exclude_guard<G>(self, guard: G) -> Self
+    where
+        G: Guard + Send + Sync + 'static,
+    {
+        self.exclude_with(move |req| guard.check(&req.guard_ctx()))
+    }
+
+    fn exclude_with<F>(mut self, excluded: F) -> Self
+    where
+        F: Fn(&ServiceRequest) -> bool + Send + Sync + 'static,
+    {
+        self.config_mut().exclusions.push(Arc::new(excluded));
+        self
+    }
+}
 
 impl<S, B> Transform<S, ServiceRequest> for RequestIDMiddleware
 where
     S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
     S::Future: 'static,
+    B: MessageBody,
 {
-    type Response = ServiceResponse<B>;
+    type Response = ServiceResponse<EitherBody<B>>;
     type Error = Error;
     type InitError = ();
     type Transform = RequestIDService<S>;
@@ -85,20 +720,23 @@ where
     fn new_transform(&self, service: S) -> Self::Future {
         ready(Ok(RequestIDService {
             wrapped_service: service,
+            config: Arc::clone(&self.config),
         }))
     }
 }
 
 pub struct RequestIDService<S> {
     wrapped_service: S,
+    config: Arc<Config>,
 }
 
-impl<S, Req> Service<ServiceRequest> for RequestIDService<S>
+impl<S, B> Service<ServiceRequest> for RequestIDService<S>
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<Req>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
     S::Future: 'static,
+    B: MessageBody,
 {
-    type Response = ServiceResponse<Req>;
+    type Response = ServiceResponse<EitherBody<B>>;
     type Error = S::Error;
     #[allow(clippy::type_complexity)]
     type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
@@ -110,23 +748,197 @@ where
         self.wrapped_service.poll_ready(ctx)
     }
 
-    fn call(&self, req: actix_web::dev::ServiceRequest) -> Self::Future {
-        let id = req.request_id().inner;
-        let fut = self.wrapped_service.call(req);
+    fn call(&self, mut req: actix_web::dev::ServiceRequest) -> Self::Future {
+        // When several middleware handle the request, the innermost one wins:
+        // let the outer one know it should step aside, and undo the header it
+        // injected so it is not taken for an incoming id.
+        if let Some(Nested(outer)) = req.extensions_mut().remove::<Nested>() {
+            outer.set(true);
+        }
+        let injected = req.extensions_mut().remove::<Injected>();
+        if let Some(injected) = injected {
+            injected.restore(req.headers_mut());
+        }
+
+        req.extensions_mut().insert(Arc::clone(&self.config));
+        req.extensions_mut().remove::<RequestID>();
+
+        if self.config.is_excluded(&req) {
+            let fut = self.wrapped_service.call(req);
+            return Box::pin(async move { Ok(fut.await?.map_into_left_body()) });
+        }
+
+        let nested = Rc::new(Cell::new(false));
+        req.extensions_mut().insert(Nested(Rc::clone(&nested)));
+
+        let rejected = match self.config.incoming_id(&req) {
+            Ok(Some(id)) => {
+                req.extensions_mut().insert(id);
+                None
+            }
+            Ok(None) => None,
+            Err(err) => Some(err),
+        };
+
+        let value = self.config.header_value(&req);
+
+        if let (Some(value), true) = (&value, self.config.inject_header) {
+            let injected = Injected::new(&self.config.header, req.headers());
+            req.extensions_mut().insert(injected);
+            req.headers_mut()
+                .insert(self.config.header.clone(), value.clone());
+        }
+
+        let echo_headers = self.config.echo_headers(value);
+        let id = req.request_id();
+
+        // Rejections are responses rather than errors, so outer middleware
+        // like `Logger` see them. They happen before routing: the request
+        // never reaches the middleware of a nested scope.
+        if let Some(err) = rejected {
+            let mut res = req.error_response(err);
+            echo_headers.apply(res.headers_mut());
+            return Box::pin(async move { Ok(res.map_into_right_body()) });
+        }
+
+        #[cfg(feature = "tracing")]
+        let (method, path) = (req.method().clone(), req.path().to_owned());
+
+        #[cfg(feature = "opentelemetry")]
+        let otel_span = otel::prepare(&req);
+
+        // Nested middleware of the matched scope or resource are called
+        // synchronously while routing, so whether one took over is known once
+        // the inner service is called.
+        let fut = current::sync_scope(id.clone(), Arc::clone(&self.config), || {
+            self.wrapped_service.call(req)
+        });
+        let handled = !nested.get();
+
+        #[cfg(feature = "tracing")]
+        let span = if handled {
+            request_span(&id, &method, &path)
+        } else {
+            tracing::Span::none()
+        };
+
+        #[cfg(feature = "opentelemetry")]
+        let otel_span = handled.then(|| otel_span.start(&id));
+
+        let fut = current::scope(handled.then_some(id), &self.config, fut);
+
+        #[cfg(feature = "tracing")]
+        let fut = tracing::Instrument::instrument(fut, span);
+
+        #[cfg(feature = "opentelemetry")]
+        let fut = opentelemetry::trace::FutureExt::with_context(
+            fut,
+            otel_span
+                .as_ref()
+                .map_or_else(opentelemetry::Context::current, |span| {
+                    span.context().clone()
+                }),
+        );
 
         Box::pin(async move {
-            let mut res = fut.await?;
+            let res = fut.await;
+
+            #[cfg(feature = "opentelemetry")]
+            if let Some(otel_span) = &otel_span {
+                otel_span.record_status(match &res {
+                    Ok(res) => res.status(),
+                    Err(err) => err.as_response_error().status_code(),
+                });
+            }
+
+            // Leave the headers to the middleware of the matched scope.
+            if nested.get() {
+                return res.map(ServiceResponse::map_into_left_body);
+            }
 
-            res.headers_mut().append(
-                HeaderName::from_static(REQUEST_ID_HEADER),
-                HeaderValue::from_str(&id).unwrap(),
-            );
+            let mut res = match res {
+                Ok(res) => res,
+                Err(mut err) => {
+                    // Errors are turned into responses further up, keep their
+                    // type and add the headers to the response built from them.
+                    if !echo_headers.headers.is_empty() {
+                        err.add_response_mapper(move |mut res| {
+                            echo_headers.apply(res.headers_mut());
+                            res
+                        });
+                    }
 
-            Ok(res)
+                    return Err(err);
+                }
+            };
+
+            echo_headers.apply(res.headers_mut());
+
+            Ok(res.map_into_left_body())
         })
     }
 }
 
+/// Set by a nested middleware handling the request in place of the one
+/// that inserted it.
+#[derive(Clone)]
+struct Nested(Rc<Cell<bool>>);
+
+/// Values of the header a middleware replaced when injecting the id.
+struct Injected {
+    header: HeaderName,
+    original: Vec<HeaderValue>,
+}
+
+impl Injected {
+    fn new(header: &HeaderName, headers: &HeaderMap) -> Self {
+        Injected {
+            header: header.clone(),
+            original: headers.get_all(header).cloned().collect(),
+        }
+    }
+
+    fn restore(self, headers: &mut HeaderMap) {
+        headers.remove(&self.header);
+        for value in self.original {
+            headers.append(self.header.clone(), value);
+        }
+    }
+}
+
+/// Span the inner service runs in.
+#[cfg(feature = "tracing")]
+fn request_span(id: &RequestID, method: &Method, path: &str) -> tracing::Span {
+    tracing::info_span!(
+        "request",
+        request_id = %id,
+        method = %method,
+        path = %path
+    )
+}
+
+/// Request id headers to add to a response.
+struct EchoedHeaders {
+    headers: Vec<(HeaderName, HeaderValue)>,
+    conflict: HeaderConflict,
+}
+
+impl EchoedHeaders {
+    fn apply(&self, headers: &mut HeaderMap) {
+        for (name, value) in &self.headers {
+            match self.conflict {
+                HeaderConflict::Overwrite => {
+                    headers.insert(name.clone(), value.clone());
+                }
+                HeaderConflict::KeepExisting if headers.contains_key(name) => {}
+                HeaderConflict::KeepExisting | HeaderConflict::Append => {
+                    headers.append(name.clone(), value.clone());
+                }
+            }
+        }
+    }
+}
+
 pub trait RequestIDMessage {
     fn request_id(&self) -> RequestID;
 }
@@ -140,12 +952,12 @@ where
             return id.clone();
         }
 
-        let new_id = RequestID {
-            inner: rand::thread_rng()
-                .sample_iter(&Alphanumeric)
-                .map(char::from)
-                .take(10)
-                .collect::<_>(),
+        let new_id = match self.extensions().get::<Arc<Config>>() {
+            Some(config) => config.generate(),
+            None => RequestID::new(
+                AlphanumericGenerator::default().generate(),
+                RequestIDSource::Generated,
+            ),
         };
 
         self.extensions_mut().insert(new_id.clone());
@@ -159,6 +971,44 @@ mod tests {
     use super::*;
     use actix_web::test::TestRequest;
     use actix_web::{http::StatusCode, test, web, App, HttpResponse};
+    use std::sync::{Mutex, Once};
+
+    static LOGGED: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+    struct CaptureLogs;
+
+    impl log::Log for CaptureLogs {
+        fn enabled(&self, _metadata: &log::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record<'_>) {
+            let line = (record.target().to_owned(), record.args().to_string());
+            LOGGED.lock().unwrap().push(line);
+        }
+
+        fn flush(&self) {}
+    }
+
+    /// Captures the log records of every test, for [`logged`].
+    pub(crate) fn capture_logs() {
+        static INIT: Once = Once::new();
+
+        INIT.call_once(|| {
+            log::set_logger(&CaptureLogs).unwrap();
+            log::set_max_level(log::LevelFilter::Debug);
+        });
+    }
+
+    /// Returns the messages logged with `target` since [`capture_logs`].
+    pub(crate) fn logged(target: &str) -> Vec<String> {
+        let logged = LOGGED.lock().unwrap();
+        logged
+            .iter()
+            .filter(|(logged, _)| logged == target)
+            .map(|(_, message)| message.clone())
+            .collect()
+    }
 
     #[actix_rt::test]
     async fn request_id_is_consistent_for_same_request() {
@@ -198,4 +1048,736 @@ mod tests {
 
         assert!(!resp.headers().get("request-id").unwrap().is_empty());
     }
+
+    #[actix_rt::test]
+    async fn middleware_honors_incoming_request_id() {
+        let app = test::init_service(App::new().wrap(RequestIDMiddleware::default()).service(
+            web::resource("/").to(|id: RequestID| async move {
+                assert_eq!(id.source(), RequestIDSource::Incoming);
+                id.to_string()
+            }),
+        ))
+        .await;
+
+        let req = test::TestRequest::with_uri("/")
+            .insert_header((REQUEST_ID_HEADER, "from-the-edge-42"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+
+        assert_eq!(
+            resp.headers().get("request-id").unwrap(),
+            "from-the-edge-42"
+        );
+        assert_eq!(test::read_body(resp).await, "from-the-edge-42");
+    }
+
+    #[actix_rt::test]
+    async fn middleware_ignores_malformed_incoming_request_id() {
+        let app = test::init_service(App::new().wrap(RequestIDMiddleware::default()).service(
+            web::resource("/").to(|id: RequestID| async move {
+                assert_eq!(id.source(), RequestIDSource::Generated);
+                HttpResponse::Ok().await
+            }),
+        ))
+        .await;
+
+        let req = test::TestRequest::with_uri("/")
+            .insert_header((REQUEST_ID_HEADER, "has spaces"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+
+        assert_ne!(resp.headers().get("request-id").unwrap(), "has spaces");
+    }
+
+    #[actix_rt::test]
+    async fn middleware_rejects_invalid_incoming_request_id() {
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    RequestIDMiddleware::default()
+                        .validation(Validation::default().uuid().on_invalid(OnInvalid::Reject)),
+                )
+                .service(web::resource("/").to(|| async { HttpResponse::Ok().await })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/")
+            .insert_header((REQUEST_ID_HEADER, "not-a-uuid"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert!(resp.headers().get(REQUEST_ID_HEADER).is_some());
+    }
+
+    #[actix_rt::test]
+    async fn middleware_ignores_incoming_request_id_from_untrusted_peer() {
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    RequestIDMiddleware::default()
+                        .trusted_peers(TrustedPeers::new(["10.0.0.0/8".parse().unwrap()])),
+                )
+                .service(web::resource("/").to(|| async { HttpResponse::Ok().await })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/")
+            .peer_addr("10.0.0.1:4000".parse().unwrap())
+            .insert_header((REQUEST_ID_HEADER, "from-ingress"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(
+            resp.headers().get(REQUEST_ID_HEADER).unwrap(),
+            "from-ingress"
+        );
+
+        let req = test::TestRequest::with_uri("/")
+            .peer_addr("203.0.113.7:4000".parse().unwrap())
+            .insert_header((REQUEST_ID_HEADER, "from-internet"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_ne!(
+            resp.headers().get(REQUEST_ID_HEADER).unwrap(),
+            "from-internet"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn innermost_middleware_configuration_wins() {
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestIDMiddleware::new())
+                .service(
+                    web::scope("/public")
+                        .wrap(RequestIDMiddleware::new().trusted_peers(TrustedPeers::new([])))
+                        .route(
+                            "/",
+                            web::get().to(|id: RequestID| async move { id.to_string() }),
+                        ),
+                )
+                .route(
+                    "/",
+                    web::get().to(|id: RequestID| async move { id.to_string() }),
+                ),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/")
+            .insert_header((REQUEST_ID_HEADER, "incoming"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get(REQUEST_ID_HEADER).unwrap(), "incoming");
+        assert_eq!(test::read_body(resp).await, "incoming");
+
+        let req = test::TestRequest::with_uri("/public/")
+            .insert_header((REQUEST_ID_HEADER, "incoming"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let header = resp.headers().get(REQUEST_ID_HEADER).unwrap().clone();
+        assert_ne!(header, "incoming");
+        assert_eq!(test::read_body(resp).await, header.as_bytes());
+    }
+
+    #[actix_rt::test]
+    async fn innermost_middleware_decides_on_rejection() {
+        let app =
+            test::init_service(
+                App::new()
+                    .wrap(RequestIDMiddleware::new())
+                    .service(
+                        web::scope("/public")
+                            .wrap(RequestIDMiddleware::new().validation(
+                                Validation::default().uuid().on_invalid(OnInvalid::Reject),
+                            ))
+                            .route(
+                                "/",
+                                web::get().to(|id: RequestID| async move { id.to_string() }),
+                            ),
+                    )
+                    .route(
+                        "/",
+                        web::get().to(|id: RequestID| async move { id.to_string() }),
+                    ),
+            )
+            .await;
+
+        let req = test::TestRequest::with_uri("/public/")
+            .insert_header((REQUEST_ID_HEADER, "not-a-uuid"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(resp.headers().get_all(REQUEST_ID_HEADER).count(), 1);
+
+        let req = test::TestRequest::with_uri("/")
+            .insert_header((REQUEST_ID_HEADER, "not-a-uuid"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(test::read_body(resp).await, "not-a-uuid");
+    }
+
+    #[actix_rt::test]
+    async fn middleware_skips_excluded_requests() {
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    RequestIDMiddleware::new()
+                        .exclude("/healthz")
+                        .exclude_prefix("/metrics")
+                        .exclude_guard(actix_web::guard::Options()),
+                )
+                .route(
+                    "/healthz",
+                    web::get().to(|| async { HttpResponse::Ok().await }),
+                )
+                .route(
+                    "/metrics/http",
+                    web::get().to(|| async { HttpResponse::Ok().await }),
+                )
+                .route(
+                    "/",
+                    web::route().to(|id: RequestID| async move { id.to_string() }),
+                ),
+        )
+        .await;
+
+        for req in [
+            test::TestRequest::with_uri("/healthz"),
+            test::TestRequest::with_uri("/metrics/http"),
+        ] {
+            let resp = test::call_service(&app, req.to_request()).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert!(resp.headers().get(REQUEST_ID_HEADER).is_none());
+        }
+
+        let req = test::TestRequest::with_uri("/")
+            .method(actix_web::http::Method::OPTIONS)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.headers().get(REQUEST_ID_HEADER).is_none());
+        assert!(!test::read_body(resp).await.is_empty());
+
+        let req = test::TestRequest::with_uri("/").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.headers().get(REQUEST_ID_HEADER).is_some());
+    }
+
+    #[cfg(feature = "regex")]
+    #[actix_rt::test]
+    async fn middleware_skips_requests_matching_regex() {
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    RequestIDMiddleware::new()
+                        .exclude_regex(regex::Regex::new(r"^/(livez|readyz)$").unwrap()),
+                )
+                .default_service(web::to(|| async { HttpResponse::Ok().await })),
+        )
+        .await;
+
+        for (path, excluded) in [("/livez", true), ("/readyz", true), ("/livez/x", false)] {
+            let req = test::TestRequest::with_uri(path).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.headers().get(REQUEST_ID_HEADER).is_none(), excluded);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn middleware_uses_configured_generator() {
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestIDMiddleware::default().generator(|| "custom-id".to_owned()))
+                .service(web::resource("/").to(|id: RequestID| async move { id.to_string() })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/").to_request();
+        let resp = test::call_service(&app, req).await;
+
+        assert_eq!(resp.headers().get("request-id").unwrap(), "custom-id");
+        assert_eq!(test::read_body(resp).await, "custom-id");
+    }
+
+    #[actix_rt::test]
+    async fn middleware_configuration_is_shared_by_workers() {
+        let middleware = RequestIDMiddleware::new().header(HeaderName::from_static("x-request-id"));
+
+        for _ in 0..2 {
+            let worker = middleware.clone();
+            assert!(Arc::ptr_eq(&worker.config, &middleware.config));
+
+            let app = test::init_service(
+                App::new()
+                    .wrap(worker)
+                    .service(web::resource("/").to(|| async { HttpResponse::Ok().await })),
+            )
+            .await;
+
+            let req = test::TestRequest::with_uri("/").to_request();
+            let resp = test::call_service(&app, req).await;
+            assert!(resp.headers().contains_key("x-request-id"));
+        }
+    }
+
+    #[actix_rt::test]
+    async fn middleware_reads_alias_headers_and_echoes_on_all() {
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    RequestIDMiddleware::default()
+                        .header(HeaderName::from_static("x-request-id"))
+                        .alias_header(HeaderName::from_static("x-correlation-id"))
+                        .echo(EchoHeaders::All),
+                )
+                .service(web::resource("/").to(|| async { HttpResponse::Ok().await })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/")
+            .insert_header(("x-correlation-id", "correlated"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+
+        assert_eq!(resp.headers().get("x-request-id").unwrap(), "correlated");
+        assert_eq!(
+            resp.headers().get("x-correlation-id").unwrap(),
+            "correlated"
+        );
+        assert!(resp.headers().get(REQUEST_ID_HEADER).is_none());
+    }
+
+    #[actix_rt::test]
+    async fn middleware_sanitizes_invalid_header_values() {
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestIDMiddleware::default().generator(|| "bad\r\nid".to_owned()))
+                .service(web::resource("/").to(|id: RequestID| async move { id.to_string() })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/").to_request();
+        let resp = test::call_service(&app, req).await;
+
+        assert_eq!(resp.headers().get(REQUEST_ID_HEADER).unwrap(), "badid");
+        assert_eq!(test::read_body(resp).await, "badid");
+    }
+
+    #[actix_rt::test]
+    async fn middleware_skips_invalid_header_values() {
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    RequestIDMiddleware::default()
+                        .generator(|| "bad\r\nid".to_owned())
+                        .invalid_header(InvalidHeaderPolicy::Skip),
+                )
+                .service(web::resource("/").to(|| async { HttpResponse::Ok().await })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/").to_request();
+        let resp = test::call_service(&app, req).await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get(REQUEST_ID_HEADER).is_none());
+    }
+
+    #[actix_rt::test]
+    async fn middleware_adds_request_id_to_error_responses() {
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestIDMiddleware::default())
+                .service(web::resource("/handler").to(|| async {
+                    Err::<HttpResponse, _>(actix_web::error::ErrorInternalServerError("boom"))
+                }))
+                .service(
+                    web::resource("/middleware")
+                        .wrap_fn(|_, _| async {
+                            Err::<ServiceResponse, _>(actix_web::error::ErrorServiceUnavailable(
+                                "unavailable",
+                            ))
+                        })
+                        .to(|| async { HttpResponse::Ok().await }),
+                ),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/handler").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(resp.headers().get(REQUEST_ID_HEADER).is_some());
+
+        // Errors of inner middleware keep their type.
+        let req = test::TestRequest::with_uri("/middleware").to_request();
+        let err = test::try_call_service(&app, req).await.unwrap_err();
+        assert!(err
+            .as_error::<actix_web::error::InternalError<&str>>()
+            .is_some());
+        let res = err.error_response();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(res.headers().get(REQUEST_ID_HEADER).is_some());
+    }
+
+    #[actix_rt::test]
+    async fn middleware_header_conflict_policy() {
+        async fn app_with(conflict: HeaderConflict) -> Vec<String> {
+            let app = test::init_service(
+                App::new()
+                    .wrap(
+                        RequestIDMiddleware::default()
+                            .generator(|| "generated".to_owned())
+                            .header_conflict(conflict),
+                    )
+                    .service(web::resource("/").to(|| async {
+                        HttpResponse::Ok()
+                            .insert_header((REQUEST_ID_HEADER, "from-handler"))
+                            .finish()
+                    })),
+            )
+            .await;
+
+            let req = test::TestRequest::with_uri("/").to_request();
+            let resp = test::call_service(&app, req).await;
+
+            resp.headers()
+                .get_all(REQUEST_ID_HEADER)
+                .map(|value| value.to_str().unwrap().to_owned())
+                .collect()
+        }
+
+        assert_eq!(app_with(HeaderConflict::Overwrite).await, ["generated"]);
+        assert_eq!(
+            app_with(HeaderConflict::KeepExisting).await,
+            ["from-handler"]
+        );
+        assert_eq!(
+            app_with(HeaderConflict::Append).await,
+            ["from-handler", "generated"]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn middleware_propagates_trace_context() {
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestIDMiddleware::default().propagation(Propagation::TraceContext))
+                .service(web::resource("/").to(|id: RequestID| async move {
+                    let context = id.trace_context().unwrap();
+                    format!("{} {:?}", context.trace_id(), context.parent_id())
+                })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/")
+            .insert_header((
+                TRACEPARENT_HEADER,
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+
+        assert_eq!(
+            resp.headers().get(REQUEST_ID_HEADER).unwrap(),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+        assert_eq!(
+            test::read_body(resp).await,
+            "4bf92f3577b34da6a3ce929d0e0e4736 Some(\"00f067aa0ba902b7\")"
+        );
+
+        let req = test::TestRequest::with_uri("/").to_request();
+        let resp = test::call_service(&app, req).await;
+        let id = resp.headers().get(REQUEST_ID_HEADER).unwrap();
+
+        assert_eq!(id.len(), 32);
+        assert!(test::read_body(resp).await.ends_with(b" None"));
+    }
+
+    #[actix_rt::test]
+    async fn middleware_propagates_b3() {
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestIDMiddleware::default().propagation(Propagation::B3(B3Format::Multi)))
+                .service(web::resource("/").to(|id: RequestID| async move {
+                    id.b3().unwrap().parent_span_id().unwrap().to_owned()
+                })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/")
+            .insert_header((
+                B3_HEADER,
+                "80f198ee56343ba864fe8b2a57d3eff7-e457b5a2e4d86bd1-1",
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+
+        assert_eq!(
+            resp.headers().get(REQUEST_ID_HEADER).unwrap(),
+            "80f198ee56343ba864fe8b2a57d3eff7"
+        );
+        assert_eq!(test::read_body(resp).await, "e457b5a2e4d86bd1");
+    }
+
+    #[actix_rt::test]
+    async fn middleware_uses_amzn_trace_id_root() {
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestIDMiddleware::default().propagation(Propagation::AmznTraceId))
+                .service(web::resource("/").to(|id: RequestID| async move {
+                    id.amzn_trace_id().unwrap().self_id().unwrap().to_owned()
+                })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/")
+            .insert_header((
+                AMZN_TRACE_ID_HEADER,
+                "Self=1-67891234-12456789abcdef012345678;Root=1-67891233-abcdef012345678912345678",
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+
+        assert_eq!(
+            resp.headers().get(REQUEST_ID_HEADER).unwrap(),
+            "1-67891233-abcdef012345678912345678"
+        );
+        assert_eq!(
+            test::read_body(resp).await,
+            "1-67891234-12456789abcdef012345678"
+        );
+    }
+
+    #[cfg(feature = "tracing")]
+    #[actix_rt::test]
+    async fn middleware_runs_handlers_in_request_span() {
+        use std::sync::Mutex;
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata, Subscriber};
+
+        type Span = (&'static str, Vec<String>);
+
+        /// Records the span each event of this module is emitted in.
+        #[derive(Default)]
+        struct Capture {
+            spans: Mutex<Vec<Span>>,
+            entered: Mutex<Vec<usize>>,
+            events: Mutex<Vec<Option<Span>>>,
+        }
+
+        struct Fields(Vec<String>);
+
+        impl Visit for Fields {
+            fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+                self.0.push(format!("{}={:?}", field.name(), value));
+            }
+        }
+
+        impl Subscriber for Capture {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut fields = Fields(Vec::new());
+                span.record(&mut fields);
+
+                let mut spans = self.spans.lock().unwrap();
+                spans.push((span.metadata().name(), fields.0));
+                Id::from_u64(spans.len() as u64)
+            }
+
+            fn record(&self, _: &Id, _: &Record<'_>) {}
+
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+            fn event(&self, event: &Event<'_>) {
+                if event.metadata().target() != module_path!() {
+                    return;
+                }
+
+                let span = self.entered.lock().unwrap().last().copied();
+                let span = span.map(|span| self.spans.lock().unwrap()[span - 1].clone());
+                self.events.lock().unwrap().push(span);
+            }
+
+            fn enter(&self, span: &Id) {
+                self.entered.lock().unwrap().push(span.into_u64() as usize);
+            }
+
+            fn exit(&self, _: &Id) {
+                self.entered.lock().unwrap().pop();
+            }
+        }
+
+        let capture = Arc::new(Capture::default());
+        let _guard = tracing::subscriber::set_default(Arc::clone(&capture));
+
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestIDMiddleware::default().generator(|| "traced".to_owned()))
+                .service(web::resource("/traced").to(|| async {
+                    tracing::info!("handled");
+                    HttpResponse::Ok().await
+                })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/traced").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let fields = ["request_id=traced", "method=GET", "path=/traced"];
+        assert_eq!(
+            *capture.events.lock().unwrap(),
+            [Some(("request", fields.map(String::from).to_vec()))]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn middleware_sets_current_request_id() {
+        fn current() -> String {
+            RequestID::current().unwrap().to_string()
+        }
+
+        let app = test::init_service(App::new().wrap(RequestIDMiddleware::default()).service(
+            web::resource("/").to(|id: RequestID| async move {
+                assert_eq!(current(), id.as_str());
+                HttpResponse::Ok().await
+            }),
+        ))
+        .await;
+
+        let req = test::TestRequest::with_uri("/").to_request();
+        let resp = test::call_service(&app, req).await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(RequestID::current().is_none());
+    }
+
+    #[actix_rt::test]
+    async fn logger_with_request_id_wraps_app() {
+        capture_logs();
+
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    RequestIDMiddleware::default()
+                        .generator(|| "logged".to_owned())
+                        .validation(Validation::default().uuid().on_invalid(OnInvalid::Reject)),
+                )
+                .wrap(
+                    actix_web::middleware::Logger::new("%s %{request_id}xo")
+                        .log_target("request_id_access_log")
+                        .log_request_id(),
+                ),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/missing").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(resp.headers().get(REQUEST_ID_HEADER).unwrap(), "logged");
+
+        // The line is logged once the body is sent.
+        test::read_body(resp).await;
+        assert_eq!(logged("request_id_access_log"), ["404 logged"]);
+
+        let req = test::TestRequest::with_uri("/missing")
+            .insert_header((REQUEST_ID_HEADER, "not-a-uuid"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(resp.headers().get(REQUEST_ID_HEADER).unwrap(), "logged");
+
+        test::read_body(resp).await;
+        assert_eq!(
+            logged("request_id_access_log"),
+            ["404 logged", "400 logged"]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn middleware_does_not_echo_when_disabled() {
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestIDMiddleware::default().echo(EchoHeaders::None))
+                .service(web::resource("/").to(|| async { HttpResponse::Ok().await })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/").to_request();
+        let resp = test::call_service(&app, req).await;
+
+        assert!(resp.headers().get(REQUEST_ID_HEADER).is_none());
+    }
+
+    #[actix_rt::test]
+    async fn middleware_injects_request_id_in_request_headers() {
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    RequestIDMiddleware::default()
+                        .alias_header(HeaderName::from_static("x-correlation-id"))
+                        .inject_header(true),
+                )
+                .service(web::resource("/").to(|req: HttpRequest| async move {
+                    req.headers()
+                        .get(REQUEST_ID_HEADER)
+                        .unwrap()
+                        .to_str()
+                        .unwrap()
+                        .to_owned()
+                })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/").to_request();
+        let resp = test::call_service(&app, req).await;
+        let id = resp.headers().get(REQUEST_ID_HEADER).unwrap().clone();
+        assert_eq!(test::read_body(resp).await, id.as_bytes());
+
+        let req = test::TestRequest::with_uri("/")
+            .insert_header(("x-correlation-id", "correlated"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(test::read_body(resp).await, "correlated");
+    }
+
+    #[actix_rt::test]
+    async fn nested_middleware_ignores_injected_header() {
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    RequestIDMiddleware::new()
+                        .trusted_peers(TrustedPeers::new([]))
+                        .inject_header(true),
+                )
+                .service(
+                    web::scope("/internal")
+                        .wrap(RequestIDMiddleware::new())
+                        .route(
+                            "/",
+                            web::get().to(|req: HttpRequest, id: RequestID| async move {
+                                let header = req.headers().get(REQUEST_ID_HEADER).cloned();
+                                format!("{:?} {:?}", id.source(), header)
+                            }),
+                        ),
+                ),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/internal/").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(test::read_body(resp).await, "Generated None");
+
+        let req = test::TestRequest::with_uri("/internal/")
+            .insert_header((REQUEST_ID_HEADER, "incoming"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get(REQUEST_ID_HEADER).unwrap(), "incoming");
+        assert_eq!(test::read_body(resp).await, "Incoming Some(\"incoming\")");
+    }
 }
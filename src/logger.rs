@@ -0,0 +1,44 @@
+//! Request id in `actix_web::middleware::Logger` access logs.
+use actix_web::middleware::Logger;
+
+use crate::RequestIDMessage;
+
+/// Label of the request id in [`Logger`] formats, used as
+/// `%{request_id}xo`.
+pub const LOGGER_REQUEST_ID: &str = "request_id";
+
+/// Adds the request id to [`Logger`] access logs.
+///
+/// The id is read from the response, so it is the id resolved by
+/// [`RequestIDMiddleware`](crate::RequestIDMiddleware), including for
+/// responses produced before reaching a handler (unmatched routes, extractor
+/// errors). Requests rejected with [`OnInvalid::Reject`] never reach the
+/// middlewares registered before it, so register [`Logger`] last for them to
+/// be logged too.
+///
+/// [`Logger`] only logs responses, not errors returned by the middlewares it
+/// wraps: those are turned into responses by actix-web once they left every
+/// middleware, so errors of other middleware are not logged.
+///
+/// [`OnInvalid::Reject`]: crate::OnInvalid::Reject
+///
+/// ```
+/// use actix_web::{middleware::Logger, App};
+/// use actix_web_requestid::{LoggerExt, RequestIDMiddleware};
+///
+/// let app = App::new()
+///     .wrap(RequestIDMiddleware::default())
+///     .wrap(Logger::new(r#"%a "%r" %s %T request_id=%{request_id}xo"#).log_request_id());
+/// ```
+pub trait LoggerExt {
+    /// Makes `%{request_id}xo` render the request id.
+    fn log_request_id(self) -> Self;
+}
+
+impl LoggerExt for Logger {
+    fn log_request_id(self) -> Self {
+        self.custom_response_replace(LOGGER_REQUEST_ID, |res| {
+            res.request().request_id().to_string()
+        })
+    }
+}
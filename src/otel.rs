@@ -0,0 +1,361 @@
+//! OpenTelemetry server spans.
+use std::convert::Infallible;
+use std::future::{ready, Ready};
+
+use actix_web::dev::{Payload, ServiceRequest};
+use actix_web::http::header::HeaderMap;
+use actix_web::http::StatusCode;
+use actix_web::{FromRequest, HttpRequest};
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::{SpanKind, Status, TraceContextExt, Tracer};
+use opentelemetry::{global, Context, KeyValue};
+
+use crate::RequestID;
+
+/// OpenTelemetry context of the request, holding the server span started by
+/// [`RequestIDMiddleware`](crate::RequestIDMiddleware).
+///
+/// The span records the [`RequestID`](crate::RequestID) as its `request_id`
+/// attribute.
+///
+/// ```
+/// use actix_web_requestid::{OtelContext, RequestID};
+/// use opentelemetry::trace::TraceContextExt;
+///
+/// async fn index(id: RequestID, cx: OtelContext) -> String {
+///     format!("{} {}", id, cx.span().span_context().trace_id())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct OtelContext(pub Context);
+
+impl std::ops::Deref for OtelContext {
+    type Target = Context;
+
+    fn deref(&self) -> &Context {
+        &self.0
+    }
+}
+
+impl FromRequest for OtelContext {
+    type Error = Infallible;
+    type Future = Ready<Result<OtelContext, Infallible>>;
+
+    #[inline]
+    fn from_request(_req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        // The middleware polls the rest of the request within the context.
+        ready(Ok(OtelContext(Context::current())))
+    }
+}
+
+/// Server span of a request, ended when dropped so it is also ended when the
+/// request is cancelled (for example when the client disconnects).
+pub(crate) struct ServerSpan {
+    cx: Context,
+}
+
+impl ServerSpan {
+    /// Context holding the span.
+    pub(crate) fn context(&self) -> &Context {
+        &self.cx
+    }
+
+    /// Records the status of the response.
+    pub(crate) fn record_status(&self, status: StatusCode) {
+        let span = self.cx.span();
+
+        span.set_attribute(KeyValue::new(
+            "http.response.status_code",
+            i64::from(status.as_u16()),
+        ));
+        if status.is_server_error() {
+            span.set_status(Status::error(status.to_string()));
+        }
+    }
+}
+
+impl Drop for ServerSpan {
+    fn drop(&mut self) {
+        self.cx.span().end();
+    }
+}
+
+/// Server span of a request, not started yet.
+pub(crate) struct PendingSpan {
+    parent: Context,
+    method: String,
+    path: String,
+}
+
+/// Extracts the remote context of a request with the global propagator.
+pub(crate) fn prepare(req: &ServiceRequest) -> PendingSpan {
+    let parent = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(req.headers()))
+    });
+
+    PendingSpan {
+        parent,
+        method: req.method().to_string(),
+        path: req.path().to_owned(),
+    }
+}
+
+impl PendingSpan {
+    /// Starts the server span, as a child of the remote context.
+    pub(crate) fn start(self, id: &RequestID) -> ServerSpan {
+        let tracer = global::tracer("actix-web-requestid");
+        let span = tracer
+            .span_builder(self.method.clone())
+            .with_kind(SpanKind::Server)
+            .with_attributes(vec![
+                KeyValue::new("http.request.method", self.method),
+                KeyValue::new("url.path", self.path),
+                KeyValue::new("request_id", id.to_string()),
+            ])
+            .start_with_context(&tracer, &self.parent);
+
+        ServerSpan {
+            cx: self.parent.with_span(span),
+        }
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use std::sync::{Arc, Mutex, OnceLock};
+    use std::time::SystemTime;
+
+    use actix_web::{test, web, App};
+    use opentelemetry::propagation::{text_map_propagator::FieldIter, Injector, TextMapPropagator};
+    use opentelemetry::trace::{
+        SpanBuilder, SpanContext, SpanId, TraceFlags, TraceId, TraceState, TracerProvider,
+    };
+    use opentelemetry::InstrumentationLibrary;
+
+    use super::*;
+    use crate::{RequestID, RequestIDMiddleware, RequestIDSource};
+
+    /// Span recorded by [`Recorder`].
+    #[derive(Debug)]
+    struct Recorded {
+        cx: SpanContext,
+        parent: SpanContext,
+        kind: Option<SpanKind>,
+        attributes: Vec<KeyValue>,
+        ended: bool,
+    }
+
+    impl Recorded {
+        fn attribute(&self, key: &str) -> Option<String> {
+            let attribute = self.attributes.iter().find(|kv| kv.key.as_str() == key)?;
+            Some(attribute.value.to_string())
+        }
+    }
+
+    /// Tracer provider keeping the spans in memory.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<Arc<Mutex<Recorded>>>>>);
+
+    impl Recorder {
+        /// Installs the recorder and the `x-parent` propagator globally, once
+        /// for all the tests.
+        fn global() -> &'static Recorder {
+            static RECORDER: OnceLock<Recorder> = OnceLock::new();
+
+            RECORDER.get_or_init(|| {
+                let recorder = Recorder::default();
+                global::set_tracer_provider(recorder.clone());
+                global::set_text_map_propagator(ParentPropagator);
+                recorder
+            })
+        }
+
+        /// Returns the span recorded with `request_id`.
+        fn find(&self, id: &str) -> Arc<Mutex<Recorded>> {
+            let spans = self.0.lock().unwrap();
+            let span = spans
+                .iter()
+                .find(|span| span.lock().unwrap().attribute("request_id").as_deref() == Some(id));
+            Arc::clone(span.unwrap())
+        }
+    }
+
+    impl TracerProvider for Recorder {
+        type Tracer = Recorder;
+
+        fn library_tracer(&self, _library: Arc<InstrumentationLibrary>) -> Recorder {
+            self.clone()
+        }
+    }
+
+    impl Tracer for Recorder {
+        type Span = RecordingSpan;
+
+        fn build_with_context(&self, builder: SpanBuilder, parent_cx: &Context) -> RecordingSpan {
+            let mut spans = self.0.lock().unwrap();
+            let parent = parent_cx.span().span_context().clone();
+            let trace_id = if parent.is_valid() {
+                parent.trace_id()
+            } else {
+                TraceId::from_hex("1").unwrap()
+            };
+            let span_id = SpanId::from_hex(&format!("{:x}", spans.len() + 1)).unwrap();
+
+            let recorded = Arc::new(Mutex::new(Recorded {
+                cx: SpanContext::new(
+                    trace_id,
+                    span_id,
+                    TraceFlags::SAMPLED,
+                    false,
+                    TraceState::default(),
+                ),
+                parent,
+                kind: builder.span_kind,
+                attributes: builder.attributes.unwrap_or_default(),
+                ended: false,
+            }));
+            spans.push(Arc::clone(&recorded));
+
+            let cx = recorded.lock().unwrap().cx.clone();
+            RecordingSpan { cx, recorded }
+        }
+    }
+
+    #[derive(Debug)]
+    struct RecordingSpan {
+        cx: SpanContext,
+        recorded: Arc<Mutex<Recorded>>,
+    }
+
+    impl opentelemetry::trace::Span for RecordingSpan {
+        fn add_event_with_timestamp<T>(&mut self, _: T, _: SystemTime, _: Vec<KeyValue>)
+        where
+            T: Into<Cow<'static, str>>,
+        {
+        }
+
+        fn span_context(&self) -> &SpanContext {
+            &self.cx
+        }
+
+        fn is_recording(&self) -> bool {
+            true
+        }
+
+        fn set_attribute(&mut self, attribute: KeyValue) {
+            self.recorded.lock().unwrap().attributes.push(attribute);
+        }
+
+        fn set_status(&mut self, _: Status) {}
+
+        fn update_name<T>(&mut self, _: T)
+        where
+            T: Into<Cow<'static, str>>,
+        {
+        }
+
+        fn end_with_timestamp(&mut self, _: SystemTime) {
+            self.recorded.lock().unwrap().ended = true;
+        }
+    }
+
+    /// Reads the remote span from `x-parent: {trace id}-{span id}`.
+    #[derive(Debug)]
+    struct ParentPropagator;
+
+    impl TextMapPropagator for ParentPropagator {
+        fn inject_context(&self, _: &Context, _: &mut dyn Injector) {}
+
+        fn extract_with_context(&self, cx: &Context, extractor: &dyn Extractor) -> Context {
+            let parent = extractor.get("x-parent").and_then(|value| {
+                let (trace_id, span_id) = value.split_once('-')?;
+                Some(SpanContext::new(
+                    TraceId::from_hex(trace_id).ok()?,
+                    SpanId::from_hex(span_id).ok()?,
+                    TraceFlags::SAMPLED,
+                    true,
+                    TraceState::default(),
+                ))
+            });
+
+            match parent {
+                Some(parent) => cx.with_remote_span_context(parent),
+                None => cx.clone(),
+            }
+        }
+
+        fn fields(&self) -> FieldIter<'_> {
+            FieldIter::new(&[])
+        }
+    }
+
+    #[actix_rt::test]
+    async fn middleware_starts_server_span() {
+        let recorder = Recorder::global();
+
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestIDMiddleware::default().generator(|| "otel-span".to_owned()))
+                .service(web::resource("/").to(|cx: OtelContext| async move {
+                    cx.span().span_context().span_id().to_string()
+                })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/")
+            .insert_header((
+                "x-parent",
+                "4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let span_id = test::read_body(resp).await;
+
+        let span = recorder.find("otel-span");
+        let span = span.lock().unwrap();
+        assert_eq!(span.cx.span_id().to_string().as_bytes(), span_id);
+        assert_eq!(
+            span.cx.trace_id().to_string(),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+        assert_eq!(span.parent.span_id().to_string(), "00f067aa0ba902b7");
+        assert!(span.parent.is_remote());
+        assert_eq!(span.kind, Some(SpanKind::Server));
+        assert_eq!(span.attribute("url.path").as_deref(), Some("/"));
+        assert_eq!(
+            span.attribute("http.response.status_code").as_deref(),
+            Some("200")
+        );
+        assert!(span.ended);
+    }
+
+    #[actix_rt::test]
+    async fn span_ends_when_dropped() {
+        let recorder = Recorder::global();
+
+        let req = test::TestRequest::default().to_srv_request();
+        let id = RequestID::new("otel-drop".to_owned(), RequestIDSource::Generated);
+        let span = prepare(&req).start(&id);
+
+        let recorded = recorder.find("otel-drop");
+        assert!(!recorded.lock().unwrap().ended);
+        assert!(!recorded.lock().unwrap().parent.is_valid());
+
+        drop(span);
+        assert!(recorded.lock().unwrap().ended);
+    }
+}
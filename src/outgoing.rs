@@ -0,0 +1,197 @@
+//! Propagation of the current request id to outgoing requests.
+#[cfg(feature = "awc")]
+pub use self::awc_ext::ClientRequestExt;
+#[cfg(feature = "reqwest")]
+pub use self::reqwest_ext::RequestIDPropagation;
+
+#[cfg(feature = "awc")]
+mod awc_ext {
+    use crate::current;
+
+    /// Adds the current request id to `awc` requests.
+    ///
+    /// The id is written in the header and propagation format configured on
+    /// the [`RequestIDMiddleware`](crate::RequestIDMiddleware) that handles
+    /// the current request. Nothing is added outside of a request.
+    ///
+    /// ```no_run
+    /// use actix_web_requestid::ClientRequestExt;
+    ///
+    /// async fn index(client: actix_web::web::Data<awc::Client>) -> String {
+    ///     let mut res = client
+    ///         .get("http://users.internal/me")
+    ///         .with_request_id()
+    ///         .send()
+    ///         .await
+    ///         .unwrap();
+    ///
+    ///     String::from_utf8(res.body().await.unwrap().to_vec()).unwrap()
+    /// }
+    /// ```
+    pub trait ClientRequestExt {
+        /// Adds the current request id headers to the request.
+        fn with_request_id(self) -> Self;
+    }
+
+    impl ClientRequestExt for awc::ClientRequest {
+        fn with_request_id(mut self) -> Self {
+            for header in current::outgoing_headers() {
+                self = self.insert_header(header);
+            }
+            self
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::{RequestID, RequestIDFutureExt, RequestIDSource, REQUEST_ID_HEADER};
+
+        #[actix_rt::test]
+        async fn adds_current_request_id() {
+            let id = RequestID::new("outgoing".to_owned(), RequestIDSource::Generated);
+
+            let req = async {
+                awc::Client::default()
+                    .get("http://localhost/")
+                    .with_request_id()
+            }
+            .with_request_id(id)
+            .await;
+
+            assert_eq!(req.headers().get(REQUEST_ID_HEADER).unwrap(), "outgoing");
+        }
+    }
+}
+
+#[cfg(feature = "reqwest")]
+mod reqwest_ext {
+    use http::Extensions;
+    use reqwest::header::{HeaderName, HeaderValue};
+    use reqwest::{Request, Response};
+    use reqwest_middleware::{Middleware, Next, Result};
+
+    use crate::{current, RequestID};
+
+    /// `reqwest-middleware` middleware adding the current request id to
+    /// outgoing requests.
+    ///
+    /// The id is written in the header and propagation format configured on
+    /// the [`RequestIDMiddleware`](crate::RequestIDMiddleware) that handles
+    /// the current request. Nothing is added outside of a request.
+    ///
+    /// ```
+    /// use actix_web_requestid::RequestIDPropagation;
+    ///
+    /// let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+    ///     .with(RequestIDPropagation::default().log_echoed_id())
+    ///     .build();
+    /// ```
+    #[derive(Debug, Clone, Default)]
+    pub struct RequestIDPropagation {
+        log_echoed_id: bool,
+    }
+
+    impl RequestIDPropagation {
+        /// Logs the id the callee echoes back in its response, at debug
+        /// level, next to the current one.
+        pub fn log_echoed_id(mut self) -> Self {
+            self.log_echoed_id = true;
+            self
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Middleware for RequestIDPropagation {
+        async fn handle(
+            &self,
+            mut req: Request,
+            extensions: &mut Extensions,
+            next: Next<'_>,
+        ) -> Result<Response> {
+            let mut primary = None;
+
+            // actix-web and reqwest do not share the same `http` version.
+            for (name, value) in current::outgoing_headers() {
+                let name = HeaderName::from_bytes(name.as_str().as_bytes());
+                let value = HeaderValue::from_bytes(value.as_bytes());
+
+                if let (Ok(name), Ok(value)) = (name, value) {
+                    primary.get_or_insert_with(|| name.clone());
+                    req.headers_mut().insert(name, value);
+                }
+            }
+
+            let res = next.run(req, extensions).await?;
+
+            if let (true, Some(primary)) = (self.log_echoed_id, primary) {
+                if let Some(echoed) = res.headers().get(&primary) {
+                    log::debug!(
+                        "request {} got {:?} echoed by {}",
+                        RequestID::current().map(String::from).unwrap_or_default(),
+                        echoed,
+                        res.url()
+                    );
+                }
+            }
+
+            Ok(res)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::sync::{Arc, Mutex};
+
+        use reqwest::header::HeaderMap;
+
+        use super::*;
+        use crate::tests::{capture_logs, logged};
+        use crate::{RequestIDFutureExt, RequestIDSource, REQUEST_ID_HEADER};
+
+        /// Answers in place of the callee, echoing another request id.
+        struct Callee(Arc<Mutex<HeaderMap>>);
+
+        #[async_trait::async_trait]
+        impl Middleware for Callee {
+            async fn handle(
+                &self,
+                req: Request,
+                _extensions: &mut Extensions,
+                _next: Next<'_>,
+            ) -> Result<Response> {
+                *self.0.lock().unwrap() = req.headers().clone();
+
+                let res = http::Response::builder()
+                    .header(REQUEST_ID_HEADER, "echoed")
+                    .body("")
+                    .unwrap();
+                Ok(res.into())
+            }
+        }
+
+        #[actix_rt::test]
+        async fn adds_current_request_id_and_logs_echoed_one() {
+            capture_logs();
+
+            let sent = Arc::new(Mutex::new(HeaderMap::new()));
+            let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+                .with(RequestIDPropagation::default().log_echoed_id())
+                .with(Callee(Arc::clone(&sent)))
+                .build();
+
+            let id = RequestID::new("outgoing".to_owned(), RequestIDSource::Generated);
+            let res = async { client.get("http://localhost/").send().await }
+                .with_request_id(id)
+                .await
+                .unwrap();
+
+            assert_eq!(res.headers()[REQUEST_ID_HEADER], "echoed");
+            assert_eq!(sent.lock().unwrap()[REQUEST_ID_HEADER], "outgoing");
+            assert_eq!(
+                logged("actix_web_requestid::outgoing::reqwest_ext"),
+                [r#"request outgoing got "echoed" echoed by http://no.url.provided.local/"#]
+            );
+        }
+    }
+}
@@ -0,0 +1,248 @@
+//! RFC 7807 `application/problem+json` error bodies.
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+
+use actix_web::body::{BodySize, EitherBody, MessageBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderMap, CONTENT_LENGTH, CONTENT_TYPE};
+use actix_web::http::StatusCode;
+use actix_web::{Error, HttpResponse};
+
+use crate::{RequestID, RequestIDMessage};
+
+/// Content type of problem documents.
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Middleware rewriting error responses into RFC 7807 problem documents
+/// embedding the request id.
+///
+/// Responses with a `4xx` or `5xx` status are rewritten when they carry an
+/// error (from a `ResponseError`, including extractor errors) or have no
+/// body (like unmatched routes). Error responses built by handlers with their
+/// own body are left alone. The `detail` member is only set for `4xx`, so
+/// internal error messages are not leaked.
+///
+/// ```json
+/// {
+///   "type": "about:blank",
+///   "title": "Not Found",
+///   "status": 404,
+///   "instance": "/missing",
+///   "request_id": "V8dQ1DvBmF"
+/// }
+/// ```
+///
+/// Wrap it inside [`RequestIDMiddleware`](crate::RequestIDMiddleware) so the
+/// request id is already resolved:
+///
+/// ```
+/// use actix_web::App;
+/// use actix_web_requestid::{ProblemJson, RequestIDMiddleware};
+///
+/// let app = App::new()
+///     .wrap(ProblemJson)
+///     .wrap(RequestIDMiddleware::default());
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProblemJson;
+
+impl<S, B> Transform<S, ServiceRequest> for ProblemJson
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: MessageBody,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ProblemJsonService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ProblemJsonService {
+            wrapped_service: service,
+        }))
+    }
+}
+
+pub struct ProblemJsonService<S> {
+    wrapped_service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for ProblemJsonService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: MessageBody,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(
+        &self,
+        ctx: &mut core::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.wrapped_service.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        // Errors carry no request to read the id from once the inner service
+        // ran, they get the id resolved so far.
+        let id = req.request_id();
+        let instance = req.path().to_owned();
+        let fut = self.wrapped_service.call(req);
+
+        Box::pin(async move {
+            let res = match fut.await {
+                Ok(res) => res,
+                Err(mut err) => {
+                    // Keep the error type for outer middleware and rewrite
+                    // the response built from it.
+                    let detail = err.to_string();
+                    err.add_response_mapper(move |res| {
+                        problem_response(
+                            res.status(),
+                            Some(detail.clone()),
+                            &id,
+                            &instance,
+                            res.headers(),
+                        )
+                    });
+
+                    return Err(err);
+                }
+            };
+
+            let status = res.status();
+            let rewrite = (status.is_client_error() || status.is_server_error())
+                && (res.response().error().is_some()
+                    || matches!(
+                        res.response().body().size(),
+                        BodySize::None | BodySize::Sized(0)
+                    ));
+
+            if !rewrite {
+                return Ok(res.map_into_left_body());
+            }
+
+            // A nested middleware may have resolved another id than the one
+            // seen on the way in.
+            let id = res.request().request_id();
+            let detail = res.response().error().map(ToString::to_string);
+            let (req, res) = res.into_parts();
+            let problem = problem_response(status, detail, &id, &instance, res.headers());
+
+            Ok(ServiceResponse::new(req, problem).map_into_right_body())
+        })
+    }
+}
+
+/// Builds a problem document response, keeping the `headers` of the
+/// response it replaces.
+fn problem_response(
+    status: StatusCode,
+    detail: Option<String>,
+    id: &RequestID,
+    instance: &str,
+    headers: &HeaderMap,
+) -> HttpResponse {
+    let mut problem = serde_json::json!({
+        "type": "about:blank",
+        "title": status.canonical_reason().unwrap_or("Unknown"),
+        "status": status.as_u16(),
+        "instance": instance,
+        "request_id": id.as_str(),
+    });
+
+    if let (Some(detail), true) = (detail, status.is_client_error()) {
+        problem["detail"] = detail.into();
+    }
+
+    let mut res = HttpResponse::build(status)
+        .content_type(PROBLEM_JSON)
+        .body(problem.to_string());
+
+    for (name, value) in headers {
+        if name != CONTENT_TYPE && name != CONTENT_LENGTH {
+            res.headers_mut().append(name.clone(), value.clone());
+        }
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RequestIDMiddleware, TrustedPeers, REQUEST_ID_HEADER};
+    use actix_web::{test, web, App};
+
+    #[actix_rt::test]
+    async fn rewrites_error_responses() {
+        let app = test::init_service(
+            App::new()
+                .wrap(ProblemJson)
+                .wrap(RequestIDMiddleware::default())
+                .service(
+                    web::resource("/json")
+                        .to(|_: web::Json<serde_json::Value>| async { HttpResponse::Ok().await }),
+                )
+                .service(
+                    web::resource("/custom")
+                        .to(|| async { HttpResponse::Conflict().body("already exists") }),
+                ),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/missing").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(resp.headers().get(CONTENT_TYPE).unwrap(), PROBLEM_JSON);
+        let id = resp.headers().get(REQUEST_ID_HEADER).unwrap().clone();
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["status"], 404);
+        assert_eq!(body["instance"], "/missing");
+        assert_eq!(body["request_id"], id.to_str().unwrap());
+
+        let req = test::TestRequest::post()
+            .uri("/json")
+            .insert_header((CONTENT_TYPE, "application/json"))
+            .set_payload("{")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body["detail"].is_string());
+
+        let req = test::TestRequest::with_uri("/custom").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(test::read_body(resp).await, "already exists");
+    }
+
+    #[actix_rt::test]
+    async fn embeds_the_id_of_nested_middleware() {
+        let app = test::init_service(
+            App::new()
+                .wrap(ProblemJson)
+                .wrap(RequestIDMiddleware::default())
+                .service(
+                    web::scope("/internal")
+                        .wrap(RequestIDMiddleware::new().trusted_peers(TrustedPeers::new([])))
+                        .service(web::resource("/").to(|| async { HttpResponse::Ok().await })),
+                ),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/internal/missing")
+            .insert_header((REQUEST_ID_HEADER, "incoming"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let id = resp.headers().get(REQUEST_ID_HEADER).unwrap().clone();
+        assert_ne!(id, "incoming");
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["request_id"], id.to_str().unwrap());
+    }
+}
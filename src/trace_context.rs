@@ -0,0 +1,174 @@
+//! W3C Trace Context propagation.
+//!
+//! See <https://www.w3.org/TR/trace-context/>.
+use actix_web::http::header::HeaderMap;
+
+use crate::hex;
+
+/// Header carrying the trace id, parent span id and trace flags.
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// Header carrying vendor specific trace data.
+pub const TRACESTATE_HEADER: &str = "tracestate";
+
+/// Trace context of a request, parsed from `traceparent` or generated.
+///
+/// The trace id is used as the [`RequestID`](crate::RequestID). The span id
+/// identifies this request, and is what should be sent as parent to
+/// downstream services.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    trace_id: String,
+    parent_id: Option<String>,
+    span_id: String,
+    flags: u8,
+    trace_state: Option<String>,
+}
+
+impl TraceContext {
+    /// Flag set when the caller may have recorded trace data.
+    pub const SAMPLED: u8 = 0x01;
+
+    /// Starts a new sampled trace.
+    pub fn generate() -> TraceContext {
+        TraceContext {
+            trace_id: hex::random_id(32),
+            parent_id: None,
+            span_id: hex::random_id(16),
+            flags: Self::SAMPLED,
+            trace_state: None,
+        }
+    }
+
+    /// Continues the trace of the incoming `traceparent` and `tracestate`
+    /// headers, if `traceparent` is valid.
+    pub fn from_headers(headers: &HeaderMap) -> Option<TraceContext> {
+        let traceparent = headers.get(TRACEPARENT_HEADER)?.to_str().ok()?;
+        let mut context = TraceContext::parse(traceparent)?;
+
+        let trace_state = headers
+            .get_all(TRACESTATE_HEADER)
+            .filter_map(|value| value.to_str().ok())
+            .collect::<Vec<_>>()
+            .join(",");
+        if !trace_state.is_empty() {
+            context.trace_state = Some(trace_state);
+        }
+
+        Some(context)
+    }
+
+    /// Parses a `traceparent` value, starting a new span within its trace.
+    pub fn parse(traceparent: &str) -> Option<TraceContext> {
+        let mut parts = traceparent.trim().split('-');
+        let version = parts.next()?;
+        let trace_id = parts.next()?;
+        let parent_id = parts.next()?;
+        let flags = parts.next()?;
+
+        // Later versions may append fields, version 00 may not.
+        let valid = hex::is_lower_hex(version, 2)
+            && version != "ff"
+            && (version != "00" || parts.next().is_none())
+            && hex::is_id(trace_id, 32)
+            && hex::is_id(parent_id, 16)
+            && hex::is_lower_hex(flags, 2);
+        if !valid {
+            return None;
+        }
+
+        let context = TraceContext {
+            trace_id: trace_id.to_owned(),
+            parent_id: Some(parent_id.to_owned()),
+            span_id: hex::random_id(16),
+            flags: u8::from_str_radix(flags, 16).ok()?,
+            trace_state: None,
+        };
+
+        Some(context)
+    }
+
+    /// Returns the 32 hex characters trace id.
+    pub fn trace_id(&self) -> &str {
+        &self.trace_id
+    }
+
+    /// Returns the span id of the caller, if the trace was propagated.
+    pub fn parent_id(&self) -> Option<&str> {
+        self.parent_id.as_deref()
+    }
+
+    /// Returns the span id of this request.
+    pub fn span_id(&self) -> &str {
+        &self.span_id
+    }
+
+    /// Returns the trace flags.
+    pub fn flags(&self) -> u8 {
+        self.flags
+    }
+
+    /// Returns whether the sampled flag is set.
+    pub fn sampled(&self) -> bool {
+        self.flags & Self::SAMPLED != 0
+    }
+
+    /// Returns the incoming `tracestate`, if any.
+    pub fn trace_state(&self) -> Option<&str> {
+        self.trace_state.as_deref()
+    }
+
+    /// Returns the `traceparent` to send to downstream services, with this
+    /// request's span as parent.
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-{:02x}", self.trace_id, self.span_id, self.flags)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_valid_traceparent() {
+        let context =
+            TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").unwrap();
+
+        assert_eq!(context.trace_id(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(context.parent_id(), Some("00f067aa0ba902b7"));
+        assert_ne!(context.span_id(), "00f067aa0ba902b7");
+        assert!(context.sampled());
+        assert_eq!(
+            &context.traceparent()[..35],
+            "00-4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_traceparent() {
+        for traceparent in [
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736",
+        ]
+        .iter()
+        {
+            assert!(
+                TraceContext::parse(traceparent).is_none(),
+                "{}",
+                traceparent
+            );
+        }
+    }
+
+    #[test]
+    fn generated_traceparent_is_valid() {
+        let context = TraceContext::generate();
+
+        assert!(TraceContext::parse(&context.traceparent()).is_some());
+        assert_eq!(context.parent_id(), None);
+    }
+}
@@ -0,0 +1,165 @@
+//! Trusting incoming ids based on the peer address.
+use std::net::{IpAddr, SocketAddr};
+
+use actix_web::dev::ServiceRequest;
+use actix_web::http::header::{FORWARDED, X_FORWARDED_FOR};
+use ipnet::IpNet;
+
+/// Networks allowed to send a request id.
+///
+/// Incoming ids are only adopted when the request comes from one of the
+/// trusted networks, ids from any other peer are ignored and a new one is
+/// generated.
+///
+/// When the application runs behind reverse proxies that do not set request
+/// ids themselves, declare them with [`TrustedPeers::proxies`]: the peer is
+/// then resolved by walking the `Forwarded` (or `X-Forwarded-For`) chain from
+/// the right, skipping those proxies.
+///
+/// ```
+/// use actix_web_requestid::{IpNet, RequestIDMiddleware, TrustedPeers};
+///
+/// let ingress: IpNet = "10.1.0.0/16".parse().unwrap();
+/// let sidecar: IpNet = "127.0.0.1/32".parse().unwrap();
+///
+/// let middleware = RequestIDMiddleware::default()
+///     .trusted_peers(TrustedPeers::new([ingress]).proxies([sidecar]));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TrustedPeers {
+    trusted: Vec<IpNet>,
+    proxies: Vec<IpNet>,
+}
+
+impl TrustedPeers {
+    /// Trusts ids sent by peers within `networks`.
+    pub fn new<I>(networks: I) -> Self
+    where
+        I: IntoIterator<Item = IpNet>,
+    {
+        TrustedPeers {
+            trusted: networks.into_iter().collect(),
+            proxies: Vec::new(),
+        }
+    }
+
+    /// Sets the reverse proxies to look through to find the peer.
+    pub fn proxies<I>(mut self, networks: I) -> Self
+    where
+        I: IntoIterator<Item = IpNet>,
+    {
+        self.proxies = networks.into_iter().collect();
+        self
+    }
+
+    /// Returns whether an id sent with this request can be trusted.
+    pub(crate) fn is_trusted(&self, req: &ServiceRequest) -> bool {
+        match self.peer(req) {
+            Some(peer) => contains(&self.trusted, &peer),
+            None => false,
+        }
+    }
+
+    /// Resolves the peer that sent the request, looking through proxies.
+    fn peer(&self, req: &ServiceRequest) -> Option<IpAddr> {
+        let mut peer = req.peer_addr()?.ip();
+        let mut chain = forwarded_chain(req).into_iter().rev();
+
+        while contains(&self.proxies, &peer) {
+            match chain.next() {
+                Some(hop) => peer = hop?,
+                None => break,
+            }
+        }
+
+        Some(peer)
+    }
+}
+
+fn contains(networks: &[IpNet], addr: &IpAddr) -> bool {
+    networks.iter().any(|network| network.contains(addr))
+}
+
+/// Returns the addresses of the `Forwarded` header, or of `X-Forwarded-For`
+/// when there is no `Forwarded` header, from client to nearest proxy.
+///
+/// Entries that are not IP addresses (obfuscated or `unknown`) are `None`.
+fn forwarded_chain(req: &ServiceRequest) -> Vec<Option<IpAddr>> {
+    let headers = req.headers();
+
+    if headers.contains_key(FORWARDED) {
+        headers
+            .get_all(FORWARDED)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.trim().split_once('='))
+                    .find(|(name, _)| name.eq_ignore_ascii_case("for"))
+                    .and_then(|(_, node)| parse_node(node.trim_matches('"')))
+            })
+            .collect()
+    } else {
+        headers
+            .get_all(X_FORWARDED_FOR)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|node| parse_node(node.trim()))
+            .collect()
+    }
+}
+
+/// Parses an address with an optional port, e.g. `192.0.2.1`,
+/// `192.0.2.1:80` or `[2001:db8::1]:4711`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    node.parse::<IpAddr>()
+        .or_else(|_| node.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .or_else(|_| node.trim_start_matches('[').trim_end_matches(']').parse())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn peers() -> TrustedPeers {
+        TrustedPeers::new(["10.1.0.0/16".parse().unwrap()])
+            .proxies(["127.0.0.1/32".parse().unwrap()])
+    }
+
+    #[test]
+    fn peer_in_trusted_network_is_trusted() {
+        let req = TestRequest::default()
+            .peer_addr("10.1.2.3:4000".parse().unwrap())
+            .to_srv_request();
+
+        assert!(peers().is_trusted(&req));
+    }
+
+    #[test]
+    fn direct_internet_traffic_is_not_trusted() {
+        let req = TestRequest::default()
+            .peer_addr("203.0.113.7:4000".parse().unwrap())
+            .insert_header((X_FORWARDED_FOR, "10.1.2.3"))
+            .to_srv_request();
+
+        assert!(!peers().is_trusted(&req));
+    }
+
+    #[test]
+    fn forwarded_chain_is_walked_through_proxies() {
+        let req = TestRequest::default()
+            .peer_addr("127.0.0.1:4000".parse().unwrap())
+            .insert_header((FORWARDED, "for=203.0.113.7, for=\"10.1.2.3:80\""))
+            .to_srv_request();
+        assert!(peers().is_trusted(&req));
+
+        let req = TestRequest::default()
+            .peer_addr("127.0.0.1:4000".parse().unwrap())
+            .insert_header((X_FORWARDED_FOR, "10.1.2.3, 203.0.113.7"))
+            .to_srv_request();
+        assert!(!peers().is_trusted(&req));
+    }
+}
@@ -0,0 +1,189 @@
+//! Validation of incoming request ids.
+use std::sync::Arc;
+
+use actix_web::http::header::HeaderValue;
+
+use crate::MAX_INCOMING_REQUEST_ID_LEN;
+
+/// What to do with an incoming id that fails validation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnInvalid {
+    /// Respond with `400 Bad Request` without calling the inner service.
+    Reject,
+    /// Ignore the incoming id and generate a new one. This is the default.
+    Replace,
+    /// Cut ids that are too long down to the maximum length. Ids that are
+    /// still invalid once truncated are replaced.
+    Truncate,
+}
+
+/// Predicate on a whole incoming id.
+type Format = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+/// Validation rules for incoming request ids.
+///
+/// By default an incoming id must be non-empty, at most
+/// [`MAX_INCOMING_REQUEST_ID_LEN`] long and only made of visible ASCII
+/// characters, otherwise it is replaced by a generated one.
+///
+/// ```
+/// use actix_web_requestid::{OnInvalid, RequestIDMiddleware, Validation};
+///
+/// let middleware = RequestIDMiddleware::default().validation(
+///     Validation::default()
+///         .max_len(36)
+///         .uuid()
+///         .on_invalid(OnInvalid::Reject),
+/// );
+/// ```
+#[derive(Clone)]
+pub struct Validation {
+    max_len: usize,
+    allowed: Arc<dyn Fn(char) -> bool + Send + Sync>,
+    format: Option<Format>,
+    on_invalid: OnInvalid,
+}
+
+/// Result of validating an incoming id.
+pub(crate) enum Validated {
+    Accept(String),
+    Replace,
+    Reject,
+}
+
+impl Default for Validation {
+    fn default() -> Self {
+        Validation {
+            max_len: MAX_INCOMING_REQUEST_ID_LEN,
+            allowed: Arc::new(|c: char| c.is_ascii_graphic()),
+            format: None,
+            on_invalid: OnInvalid::Replace,
+        }
+    }
+}
+
+impl Validation {
+    /// Sets the maximum length of an incoming id.
+    pub fn max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len;
+        self
+    }
+
+    /// Sets the characters allowed in an incoming id.
+    ///
+    /// Only visible ASCII characters can be allowed: anything else is never
+    /// accepted, whatever this predicate returns.
+    pub fn allowed_chars<F>(mut self, allowed: F) -> Self
+    where
+        F: Fn(char) -> bool + Send + Sync + 'static,
+    {
+        self.allowed = Arc::new(allowed);
+        self
+    }
+
+    /// Requires incoming ids to match a format.
+    pub fn format<F>(mut self, format: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.format = Some(Arc::new(format));
+        self
+    }
+
+    /// Requires incoming ids to be hyphenated UUIDs.
+    pub fn uuid(self) -> Self {
+        self.format(is_uuid)
+    }
+
+    /// Requires incoming ids to match a regular expression.
+    #[cfg(feature = "regex")]
+    pub fn regex(self, regex: regex::Regex) -> Self {
+        self.format(move |value| regex.is_match(value))
+    }
+
+    /// Sets what to do with an incoming id that fails validation.
+    pub fn on_invalid(mut self, on_invalid: OnInvalid) -> Self {
+        self.on_invalid = on_invalid;
+        self
+    }
+
+    pub(crate) fn validate(&self, value: &HeaderValue) -> Validated {
+        let mut value = match value.to_str() {
+            Ok(value) => value,
+            Err(_) => return self.invalid(),
+        };
+
+        if value.len() > self.max_len {
+            if self.on_invalid != OnInvalid::Truncate {
+                return self.invalid();
+            }
+            // `to_str` only succeeds on ASCII, so any index is a char boundary.
+            value = &value[..self.max_len];
+        }
+
+        let valid = !value.is_empty()
+            && value
+                .chars()
+                .all(|c| c.is_ascii_graphic() && (self.allowed)(c))
+            && self.format.as_ref().is_none_or(|format| format(value));
+
+        if valid {
+            Validated::Accept(value.to_owned())
+        } else {
+            self.invalid()
+        }
+    }
+
+    fn invalid(&self) -> Validated {
+        match self.on_invalid {
+            OnInvalid::Reject => Validated::Reject,
+            OnInvalid::Replace | OnInvalid::Truncate => Validated::Replace,
+        }
+    }
+}
+
+fn is_uuid(value: &str) -> bool {
+    value.len() == 36
+        && value.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accepted(validation: &Validation, value: &str) -> Option<String> {
+        match validation.validate(&HeaderValue::from_str(value).unwrap()) {
+            Validated::Accept(id) => Some(id),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn default_validation_rejects_spaces_and_long_ids() {
+        let validation = Validation::default();
+
+        assert_eq!(accepted(&validation, "abc-123"), Some("abc-123".to_owned()));
+        assert_eq!(accepted(&validation, "abc 123"), None);
+        assert_eq!(accepted(&validation, &"a".repeat(129)), None);
+    }
+
+    #[test]
+    fn truncate_cuts_long_ids() {
+        let validation = Validation::default()
+            .max_len(4)
+            .on_invalid(OnInvalid::Truncate);
+
+        assert_eq!(accepted(&validation, "abcdef"), Some("abcd".to_owned()));
+    }
+
+    #[test]
+    fn uuid_format_is_enforced() {
+        let validation = Validation::default().uuid();
+
+        assert!(accepted(&validation, "67e55044-10b1-426f-9247-bb680e5fe0c8").is_some());
+        assert!(accepted(&validation, "67e55044").is_none());
+    }
+}